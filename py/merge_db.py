@@ -0,0 +1,37 @@
+import sqlite3
+import random
+import sys
+
+if len(sys.argv) < 3:
+    print("Invalid number of arguments!")
+    print("Usage python merge_db.py <out_db_file> <db_file> [<db_file> ...]")
+    exit(0)
+
+__out_db_file = sys.argv[1]
+__db_files = sys.argv[2:]
+
+rows = []
+
+for db_file in __db_files:
+    conn = sqlite3.connect(db_file)
+    cursor = conn.cursor()
+
+    cursor.execute("SELECT * FROM positions")
+    rows += cursor.fetchall()
+
+    conn.close()
+
+# positions of both sides are mixed, so each batch has white and black to move
+random.shuffle(rows)
+
+conn = sqlite3.connect(__out_db_file)
+cursor = conn.cursor()
+
+cursor.execute('''CREATE TABLE IF NOT EXISTS positions
+             (fen text PRIMARY KEY, evaluation real)''')
+cursor.executemany("INSERT OR IGNORE INTO positions VALUES (?,?)", rows)
+
+conn.commit()
+conn.close()
+
+print("Merged {} positions into {}".format(len(rows), __out_db_file))
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use log::warn;
+
+use ndarray::{Array1, Array2};
+use serde::Serialize;
+
+use nevermind_neu::models::*;
+use nevermind_neu::orchestra::*;
+
+use pleco::{Board, Player, ScoringMove};
+
+use crate::dataloader::LABEL_LIMIT;
+use crate::eval_cache::EvalCache;
+use crate::sqlite_dataset::*;
+use crate::train::Models;
+
+/// Most positions sent to the network at once, the last batch holds the rest only
+pub const LEAF_BATCH_SIZE: usize = 32;
+
+/// Bound of the network score in centipawns, far below mate scores of the search.
+/// Networks are trained on labels normalized from `LABEL_LIMIT` pawns,
+/// bundles with another limit are refused on load.
+pub const MAX_EVAL_V: i16 = (LABEL_LIMIT * 100.0) as i16;
+
+/// Network output (0..1 from white's side) in centipawns for `side`
+pub fn net_out_to_score(out: f32, side: Player) -> i16 {
+    let score =
+        ((out - 0.5) * 2.0 * MAX_EVAL_V as f32).clamp(-MAX_EVAL_V as f32, MAX_EVAL_V as f32) as i16;
+
+    match side {
+        Player::White => score,
+        Player::Black => -score,
+    }
+}
+
+/// Inverse of `net_out_to_score` for the side to move, output in 0..1
+pub fn score_to_net_out(score: i16) -> f32 {
+    (score as f32 / (2.0 * MAX_EVAL_V as f32) + 0.5).clamp(0.0, 1.0)
+}
+
+/// Evaluates sibling leaves of the search as mini-batches.
+/// Uses its own orchestras, because the single position ones are built with `test_batch_size(1)`.
+pub struct LeafBatch<T: Model + Serialize + Clone> {
+    net: Orchestra<T>,
+    net_black: Orchestra<T>,
+    /// Networks get the side to move in their input
+    side_input: bool,
+    /// Scores of prefetched positions keyed by zobrist, consumed by the leaf evaluation.
+    /// Like all scores of the batch they're from the side to move of the position.
+    scores: HashMap<u64, i16>,
+    /// Network calls and positions evaluated by them, for statistics
+    pub batches: u64,
+    pub positions: u64,
+}
+
+impl<T: Model + Serialize + Clone> LeafBatch<T> {
+    pub fn new(models: Models<T>) -> Self {
+        Self {
+            net: Orchestra::new_for_eval(models.white).test_batch_size(LEAF_BATCH_SIZE),
+            net_black: Orchestra::new_for_eval(models.black).test_batch_size(LEAF_BATCH_SIZE),
+            side_input: models.side_input,
+            scores: HashMap::new(),
+            batches: 0,
+            positions: 0,
+        }
+    }
+
+    /// Network input of the position
+    pub fn encode(&self, board: &mut Board) -> Array1<f32> {
+        let entry = if self.side_input {
+            encode_board_with_side(board, 0.0)
+        } else {
+            encode_board(board, 0.0)
+        };
+
+        entry.unwrap().input
+    }
+
+    /// Prefetched score of the position, if any
+    pub fn get(&self, key: u64) -> Option<i16> {
+        self.scores.get(&key).cloned()
+    }
+
+    /// Evaluates positions after each of `moves` in as few network calls as possible.
+    /// All children have the same side to move, so a single network is used.
+    /// Previously prefetched scores are dropped, the caller consumes them before the next prefetch.
+    /// Positions known to `cache` are skipped. On a network error nothing is prefetched
+    /// and the leaves are evaluated one by one.
+    pub fn prefetch(&mut self, board: &mut Board, moves: &[ScoringMove], cache: &EvalCache) {
+        self.scores.clear();
+
+        let mut keys = Vec::with_capacity(moves.len());
+        let mut inputs = Vec::with_capacity(moves.len());
+
+        for m in moves.iter() {
+            board.apply_move(m.bit_move);
+
+            let key = board.zobrist();
+            if !keys.contains(&key) && !cache.contains(board) {
+                keys.push(key);
+                inputs.push(self.encode(board));
+            }
+
+            board.undo_move();
+        }
+
+        match self.eval_batch(&inputs, board.turn().other_player()) {
+            Ok(scores) => self.scores.extend(keys.into_iter().zip(scores)),
+            Err(e) => warn!("Batched evaluation failed : {}", e),
+        }
+    }
+
+    /// Network scores of encoded positions with `side` to move, in as few calls as possible.
+    /// Scores are in centipawns from `side`.
+    pub fn eval_batch(
+        &mut self,
+        inputs: &[Array1<f32>],
+        side: Player,
+    ) -> Result<Vec<i16>, Box<dyn Error>> {
+        let net = if side == Player::White {
+            &mut self.net
+        } else {
+            &mut self.net_black
+        };
+
+        let mut scores = Vec::with_capacity(inputs.len());
+
+        for chunk in inputs.chunks(LEAF_BATCH_SIZE) {
+            let mut batch = Array2::<f32>::zeros((chunk.len(), chunk[0].len()));
+
+            for (mut row, inp) in batch.outer_iter_mut().zip(chunk.iter()) {
+                row.assign(inp);
+            }
+
+            let out = net.eval(batch)?;
+            let out_b = out.borrow();
+
+            scores.extend((0..chunk.len()).map(|i| net_out_to_score(out_b[[i, 0]], side)));
+
+            self.batches += 1;
+            self.positions += chunk.len() as u64;
+        }
+
+        Ok(scores)
+    }
+
+    pub fn clear(&mut self) {
+        self.scores.clear();
+        self.batches = 0;
+        self.positions = 0;
+    }
+}
@@ -0,0 +1,415 @@
+use clap::ArgMatches;
+use log::{info, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use pleco::{BitMove, Board, PieceType, Player};
+
+use crate::book::*;
+
+/// Book weights are stored as u16, bigger ones are scaled down
+const MAX_WEIGHT: u64 = u16::MAX as u64;
+/// Evaluations in pawns are turned into the expected score like elo differences
+const EVAL_SCALE: f64 = 4.0;
+
+/// Occurrences of a move in a position and the score its side got with it
+#[derive(Default, Clone, Copy)]
+struct MoveStats {
+    count: u64,
+    /// Win 2, draw 1, loss 0 for games, expected score times 100 for evaluations
+    points: u64,
+}
+
+type BookStats = HashMap<(u64, u16), MoveStats>;
+
+/// Builds a polyglot book from pgn games or sqlite positions databases,
+/// only moves seen at least `--min_count` times in the first `--max_ply` plies are kept
+pub fn build_book(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let out = args.get_one::<String>("Out").unwrap();
+    let min_count = *args.get_one::<u64>("MinCount").unwrap();
+    let max_ply = *args.get_one::<u16>("MaxPly").unwrap();
+
+    let mut stats = BookStats::new();
+
+    if let Some(paths) = args.get_many::<String>("Pgn") {
+        for p in paths {
+            add_pgn(&mut stats, p, max_ply)?;
+        }
+    }
+
+    if let Some(paths) = args.get_many::<String>("DbPath") {
+        add_dbs(&mut stats, &paths.collect::<Vec<_>>(), max_ply)?;
+    }
+
+    stats.retain(|_, s| s.count >= min_count && s.points > 0);
+
+    // single scale for the whole book, so weights of a position keep their ratios
+    let max_points = stats.values().map(|s| s.points).max().unwrap_or(0);
+    let scale = if max_points > MAX_WEIGHT {
+        MAX_WEIGHT as f64 / max_points as f64
+    } else {
+        1.0
+    };
+
+    let entries = stats
+        .iter()
+        .map(|((key, mv), s)| BookEntry {
+            key: *key,
+            mv: *mv,
+            weight: ((s.points as f64 * scale).round() as u16).max(1),
+        })
+        .collect();
+
+    let book = Book::from_entries(entries, 0);
+    book.save(out)?;
+
+    info!("Book with {} entries saved to {}", book.len(), out);
+
+    Ok(())
+}
+
+fn add_move(stats: &mut BookStats, key: u64, mv: BitMove, count: u64, points: u64) {
+    let s = stats.entry((key, encode_move(mv))).or_default();
+
+    s.count += count;
+    s.points += points;
+}
+
+/// Moves of every game up to `max_ply`, weighted by the game result
+fn add_pgn(stats: &mut BookStats, path: &str, max_ply: u16) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let mut games = 0;
+    let mut result = None;
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            // tags of the next game follow the previous movetext
+            if !movetext.is_empty() {
+                games += add_game(stats, &movetext, result.take(), max_ply) as usize;
+                movetext.clear();
+            }
+
+            if let Some(r) = line.strip_prefix("[Result \"") {
+                result = r.split('"').next().map(|r| r.to_string());
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push(' ');
+        }
+    }
+
+    if !movetext.trim().is_empty() {
+        games += add_game(stats, &movetext, result.take(), max_ply) as usize;
+    }
+
+    info!("{} games read from {}", games, path);
+
+    Ok(())
+}
+
+/// Points of the side to move for a game result, unknown results count as draws
+fn result_points(result: &str, side: Player) -> u64 {
+    match (result, side) {
+        ("1-0", Player::White) | ("0-1", Player::Black) => 2,
+        ("1-0", Player::Black) | ("0-1", Player::White) => 0,
+        _ => 1,
+    }
+}
+
+/// Returns false if the game has an illegal or unknown move
+fn add_game(stats: &mut BookStats, movetext: &str, result: Option<String>, max_ply: u16) -> bool {
+    let tokens = movetext_tokens(movetext);
+    let result = result
+        .or_else(|| {
+            tokens
+                .last()
+                .filter(|t| is_result(t))
+                .map(|t| t.to_string())
+        })
+        .unwrap_or_else(|| "*".to_string());
+
+    let mut board = Board::start_pos();
+
+    for (ply, san) in tokens.iter().filter(|t| !is_result(t)).enumerate() {
+        if ply >= max_ply as usize {
+            break;
+        }
+
+        let mv = match san_to_move(&board, san) {
+            Some(m) => m,
+            None => {
+                warn!(
+                    "Unknown move {} at ply {}, rest of the game is skipped",
+                    san, ply
+                );
+                return false;
+            }
+        };
+
+        let points = result_points(&result, board.turn());
+        add_move(stats, polyglot_key(&board), mv, 1, points);
+        board.apply_move(mv);
+    }
+
+    true
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+/// Moves and the result of the movetext without comments, variations, NAGs and move numbers
+fn movetext_tokens(movetext: &str) -> Vec<String> {
+    let mut clean = String::with_capacity(movetext.len());
+    let mut comment = false;
+    let mut variation = 0;
+
+    for c in movetext.chars() {
+        match c {
+            '{' => comment = true,
+            '}' => comment = false,
+            '(' if !comment => variation += 1,
+            ')' if !comment => variation -= 1,
+            _ if comment || variation > 0 => {}
+            _ => clean.push(c),
+        }
+    }
+
+    clean
+        .split_whitespace()
+        .filter(|t| !t.starts_with('$'))
+        // `12.`, `12...` and `12.e4`
+        .map(|t| t.rsplit('.').next().unwrap_or(t))
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            t.trim_end_matches(|c| matches!(c, '+' | '#' | '!' | '?'))
+                .to_string()
+        })
+        .collect()
+}
+
+/// Legal move of the board for a move in standard algebraic notation
+fn san_to_move(board: &Board, san: &str) -> Option<BitMove> {
+    let moves = board.generate_moves();
+
+    if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+        let king_side = san.len() == 3;
+
+        return moves
+            .iter()
+            .find(|m| m.is_castle() && (m.to_string().as_bytes()[2] == b'g') == king_side)
+            .cloned();
+    }
+
+    let san = san.replace(['x', '='], "");
+    let mut chars: Vec<char> = san.chars().collect();
+
+    let piece = match chars.first()? {
+        'N' => PieceType::N,
+        'B' => PieceType::B,
+        'R' => PieceType::R,
+        'Q' => PieceType::Q,
+        'K' => PieceType::K,
+        _ => PieceType::P,
+    };
+
+    if piece != PieceType::P {
+        chars.remove(0);
+    }
+
+    let promo = match chars.last()? {
+        'N' => Some(PieceType::N),
+        'B' => Some(PieceType::B),
+        'R' => Some(PieceType::R),
+        'Q' => Some(PieceType::Q),
+        _ => None,
+    };
+
+    if promo.is_some() {
+        chars.pop();
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+
+    let dest: String = chars[chars.len() - 2..].iter().collect();
+    // file and/or rank of the source square
+    let hint: Vec<char> = chars[..chars.len() - 2].to_vec();
+
+    let mut found = moves.iter().filter(|m| {
+        let uci = m.to_string();
+
+        board.moved_piece(**m).type_of() == piece
+            && !m.is_castle()
+            && uci[2..4] == dest
+            && promo == m.is_promo().then(|| m.promo_piece())
+            && hint.iter().all(|h| uci[0..2].contains(*h))
+    });
+
+    match (found.next(), found.next()) {
+        (Some(m), None) => Some(*m),
+        _ => None,
+    }
+}
+
+/// Moves between positions of sqlite databases (`positions` table of fen and evaluation),
+/// weighted by the evaluation of the position they lead to. White and black databases
+/// are read together, since each one has positions of a single side to move.
+fn add_dbs(stats: &mut BookStats, paths: &[&String], max_ply: u16) -> Result<(), Box<dyn Error>> {
+    // occurrences and summed evaluations of each position
+    let mut positions: HashMap<u64, (u64, f64)> = HashMap::new();
+    let mut boards: HashMap<u64, Board> = HashMap::new();
+
+    for path in paths {
+        let connection = rusqlite::Connection::open(path)?;
+        let mut stmt = connection.prepare("SELECT fen, evaluation FROM positions")?;
+        let mut rows = stmt.query([])?;
+        let mut cnt = 0;
+
+        while let Some(row) = rows.next()? {
+            let fen: String = row.get_unwrap(0);
+            let eval: f64 = row.get_unwrap(1);
+
+            let board = match Board::from_fen(&fen) {
+                Ok(b) => b,
+                Err(_) => continue,
+            };
+
+            let key = polyglot_key(&board);
+            let p = positions.entry(key).or_default();
+            p.0 += 1;
+            p.1 += eval;
+            cnt += 1;
+
+            if board.moves_played() < max_ply {
+                boards.entry(key).or_insert(board);
+            }
+        }
+
+        info!("{} positions read from {}", cnt, path);
+    }
+
+    for (key, board) in boards.iter_mut() {
+        for mv in board.generate_moves().iter() {
+            board.apply_move(*mv);
+            let child = positions.get(&polyglot_key(board)).cloned();
+            board.undo_move();
+
+            if let Some((count, eval_sum)) = child {
+                // evaluations are from white's side
+                let eval = match board.turn() {
+                    Player::White => eval_sum / count as f64,
+                    Player::Black => -eval_sum / count as f64,
+                };
+                let expected = 1.0 / (1.0 + 10f64.powf(-eval / EVAL_SCALE));
+
+                let points = (expected * 100.0 * count as f64).round() as u64;
+                add_move(stats, *key, *mv, count, points);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san_uci(fen: &str, san: &str) -> Option<String> {
+        let board = Board::from_fen(fen).unwrap();
+        san_to_move(&board, san).map(|m| m.to_string())
+    }
+
+    #[test]
+    fn movetext_skips_comments_variations_and_nags() {
+        let tokens = movetext_tokens(
+            "1. e4 {best by test} e5 $1 2.Nf3 (2. f4 exf4 (2... d5 3. exd5) 3. Nf3) \
+             2... Nc6!? 3. Bb5+ a6 4. Bxc6# 1-0",
+        );
+
+        assert_eq!(
+            tokens,
+            ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Bxc6", "1-0"]
+        );
+    }
+
+    #[test]
+    fn san_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+
+        assert_eq!(san_uci(fen, "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(san_uci(fen, "O-O-O").as_deref(), Some("e1c1"));
+        assert_eq!(san_uci(fen, "0-0").as_deref(), Some("e1g1"));
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+
+        assert_eq!(san_uci(fen, "O-O").as_deref(), Some("e8g8"));
+        assert_eq!(san_uci(fen, "O-O-O").as_deref(), Some("e8c8"));
+    }
+
+    #[test]
+    fn san_promotion() {
+        let fen = "3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1";
+
+        assert_eq!(san_uci(fen, "e8=Q").as_deref(), Some("e7e8q"));
+        assert_eq!(san_uci(fen, "e8N").as_deref(), Some("e7e8n"));
+        assert_eq!(san_uci(fen, "exd8=R").as_deref(), Some("e7d8r"));
+        // promotion piece is required
+        assert_eq!(san_uci(fen, "e8"), None);
+    }
+
+    #[test]
+    fn san_disambiguation() {
+        let fen = "4k3/8/8/R7/8/8/8/RN2KN2 w - - 0 1";
+
+        assert_eq!(san_uci(fen, "Nd2"), None);
+        assert_eq!(san_uci(fen, "Nbd2").as_deref(), Some("b1d2"));
+        assert_eq!(san_uci(fen, "Nfd2").as_deref(), Some("f1d2"));
+        assert_eq!(san_uci(fen, "Ra3"), None);
+        assert_eq!(san_uci(fen, "R1a3").as_deref(), Some("a1a3"));
+        assert_eq!(san_uci(fen, "R5xa3").as_deref(), Some("a5a3"));
+        assert_eq!(san_uci(fen, "Ra1a3").as_deref(), Some("a1a3"));
+    }
+
+    #[test]
+    fn pgn_moves_are_found_in_the_book() {
+        let mut stats = BookStats::new();
+        let game = "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. O-O+ Nf6 1-0";
+
+        assert!(add_game(&mut stats, game, None, 20));
+
+        let entries = stats
+            .iter()
+            .map(|((key, mv), s)| BookEntry {
+                key: *key,
+                mv: *mv,
+                weight: s.points as u16,
+            })
+            .collect();
+        let book = Book::from_entries(entries, 20);
+
+        let first = book.moves(&Board::start_pos());
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].0.to_string(), "e2e4");
+        // white won the game
+        assert_eq!(first[0].1, 2);
+
+        let mut board = Board::start_pos();
+
+        for m in ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "f8c5"] {
+            assert!(board.apply_uci_move(m));
+        }
+
+        let castle = book.moves(&board);
+        assert_eq!(castle.len(), 1);
+        assert!(castle[0].0.is_castle());
+        assert_eq!(castle[0].0.to_string(), "e1g1");
+    }
+}
@@ -0,0 +1,308 @@
+use clap::ArgMatches;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dataloader::LABEL_LIMIT;
+use crate::model_cfg::ModelCfg;
+use crate::sqlite_dataset::{ENCODING_VERSION, INPUT_SIZE, INPUT_SIZE_WITH_SIDE};
+
+const FILE_MAGIC: &[u8; 4] = b"CTMB";
+
+/// Number of temporary weight files of this process, keeps their names unique
+static TEMP_STATES: AtomicUsize = AtomicUsize::new(0);
+
+/// Models save and load states through files only, weights of bundles pass through such one
+fn temp_state_path() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "chess_trainer_{}_{}.state",
+        process::id(),
+        TEMP_STATES.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Side to move the model evaluates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    White,
+    Black,
+    /// Single model with the side to move in its input
+    Both,
+}
+
+impl Side {
+    /// Side of the model trained by `train`, if it was given
+    pub fn from_args(args: &ArgMatches) -> Option<Self> {
+        if args.contains_id("SideToMove") {
+            return Some(Side::Both);
+        }
+
+        args.get_one::<String>("Side").and_then(|s| Self::parse(s))
+    }
+
+    /// `white`, `black` or `both`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "white" => Some(Side::White),
+            "black" => Some(Side::Black),
+            "both" => Some(Side::Both),
+            _ => None,
+        }
+    }
+
+    pub fn input_size(self) -> usize {
+        match self {
+            Side::Both => INPUT_SIZE_WITH_SIDE,
+            _ => INPUT_SIZE,
+        }
+    }
+}
+
+/// Layout of the network input, see `sqlite_dataset::encode_board`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encoding {
+    pub version: u32,
+    pub input_size: usize,
+}
+
+/// Evaluations in pawns are clamped to `-limit..limit` and scaled to 0..1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Labels {
+    pub limit: f32,
+}
+
+/// Where the weights came from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Unknown for packed bare states unless it's given to `bundle`
+    #[serde(default)]
+    pub dataset: Option<String>,
+    #[serde(default)]
+    pub epochs: Option<usize>,
+    /// State the training was continued from
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Unix time of the training start or of packing a bare state
+    pub created: u64,
+    pub trainer_version: String,
+}
+
+impl Provenance {
+    pub fn new(dataset: Option<String>, epochs: Option<usize>, parent: Option<String>) -> Self {
+        Self {
+            dataset,
+            epochs,
+            parent,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            trainer_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// Everything the weights can't be used without
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelMeta {
+    pub arch: ModelCfg,
+    pub encoding: Encoding,
+    pub side: Side,
+    pub labels: Labels,
+    pub provenance: Provenance,
+}
+
+impl ModelMeta {
+    /// `label_limit` is the clamp of evaluations the weights were trained on
+    pub fn new(arch: ModelCfg, side: Side, label_limit: f32, provenance: Provenance) -> Self {
+        Self {
+            arch,
+            encoding: Encoding {
+                version: ENCODING_VERSION,
+                input_size: side.input_size(),
+            },
+            side,
+            labels: Labels { limit: label_limit },
+            provenance,
+        }
+    }
+
+    /// Refuses a model which can't be used as `side` network by this build.
+    /// `arch` is the one of `--model_cfg` if it was given.
+    pub fn check(
+        &self,
+        name: &str,
+        side: Side,
+        arch: Option<&ModelCfg>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.side != side {
+            return Err(format!(
+                "{} is a {:?} model, but it's loaded as {:?} one",
+                name, self.side, side
+            )
+            .into());
+        }
+
+        if self.encoding.version != ENCODING_VERSION
+            || self.encoding.input_size != side.input_size()
+        {
+            return Err(format!(
+                "{} expects input encoding v{} of {} values, this build encodes v{} of {}",
+                name,
+                self.encoding.version,
+                self.encoding.input_size,
+                ENCODING_VERSION,
+                side.input_size()
+            )
+            .into());
+        }
+
+        if self.labels.limit != LABEL_LIMIT {
+            return Err(format!(
+                "{} was trained on evaluations clamped to ±{} pawns, search expects ±{}",
+                name, self.labels.limit, LABEL_LIMIT
+            )
+            .into());
+        }
+
+        if let Some(arch) = arch {
+            if *arch != self.arch {
+                return Err(format!(
+                    "{} was trained with another architecture than --model_cfg describes, drop --model_cfg to use the bundled one",
+                    name
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Weights of a snapshot together with their `ModelMeta`.
+/// File is the magic, length of the yaml description, the description and the state file.
+pub struct Bundle {
+    pub meta: ModelMeta,
+    weights: Vec<u8>,
+}
+
+impl Bundle {
+    /// Bundles start with the magic, bare `.state` files don't
+    pub fn is_bundle(path: &str) -> Result<bool, Box<dyn Error>> {
+        let mut magic = [0u8; 4];
+        let read = File::open(path)?.read(&mut magic)?;
+
+        Ok(read == magic.len() && &magic == FILE_MAGIC)
+    }
+
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        let header = FILE_MAGIC.len() + 4;
+
+        if data.len() < header || !data.starts_with(FILE_MAGIC) {
+            return Err(format!("{} isn't a model bundle", path).into());
+        }
+
+        let meta_len = u32::from_le_bytes(data[FILE_MAGIC.len()..header].try_into()?) as usize;
+
+        if data.len() < header + meta_len {
+            return Err(format!("Model bundle {} is truncated", path).into());
+        }
+
+        let meta = serde_yaml::from_slice(&data[header..header + meta_len])?;
+
+        Ok(Self {
+            meta,
+            weights: data[header + meta_len..].to_vec(),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let meta = serde_yaml::to_string(&self.meta)?;
+        let mut data = Vec::with_capacity(FILE_MAGIC.len() + 4 + meta.len() + self.weights.len());
+
+        data.extend_from_slice(FILE_MAGIC);
+        data.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+        data.extend_from_slice(meta.as_bytes());
+        data.extend_from_slice(&self.weights);
+
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Bundle of the weights `save` writes to the given state file
+    pub fn from_saved(
+        meta: ModelMeta,
+        save: impl FnOnce(&str) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = temp_state_path();
+
+        let res = save(path.to_str().ok_or("Non UTF-8 temporary path")?);
+        let weights = res.and_then(|_| Ok(fs::read(&path)?));
+        let _ = fs::remove_file(&path);
+
+        Ok(Self {
+            meta,
+            weights: weights?,
+        })
+    }
+
+    /// Unpacks the weights to a state file for `load`
+    pub fn with_weights<R>(
+        &self,
+        load: impl FnOnce(&str) -> Result<R, Box<dyn Error>>,
+    ) -> Result<R, Box<dyn Error>> {
+        let path = temp_state_path();
+
+        fs::write(&path, &self.weights)?;
+        let res = load(path.to_str().ok_or("Non UTF-8 temporary path")?);
+        fs::remove_file(&path)?;
+
+        res
+    }
+}
+
+/// Packs a bare state, like snapshots `train` saves during the training
+/// or states of older versions, with the description given in the arguments
+pub fn bundle(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let state = args.get_one::<String>("State").unwrap();
+    let out = args.get_one::<String>("Out").unwrap();
+
+    if Bundle::is_bundle(state)? {
+        return Err(format!("{} is already a model bundle", state).into());
+    }
+
+    let side = args.get_one::<String>("Side").unwrap();
+
+    let meta = ModelMeta::new(
+        ModelCfg::from_args(args)?.unwrap_or_default(),
+        Side::parse(side).ok_or_else(|| format!("Unknown side {}", side))?,
+        *args.get_one::<f32>("LabelLimit").unwrap(),
+        Provenance::new(
+            args.get_one::<String>("Dataset").cloned(),
+            args.get_one::<usize>("EpochsNum").copied(),
+            None,
+        ),
+    );
+
+    let bundle = Bundle {
+        meta,
+        weights: fs::read(state)?,
+    };
+
+    bundle.save(out)?;
+    info!(
+        "Bundled {} as {:?} model into {}",
+        state, bundle.meta.side, out
+    );
+
+    Ok(())
+}
@@ -6,13 +6,20 @@ use std::cell::RefCell;
 use log::info;
 use rand::thread_rng;
 
-use crate::sqlite_dataset::encode_board;
+use crate::sqlite_dataset::{encode_board, encode_board_with_side};
+
+/// Evaluations in pawns are clamped to it before scaling to 0..1
+pub const LABEL_LIMIT: f32 = 20.0;
 
 pub struct SqliteChessDataloader {
     sqlite_con: rusqlite::Connection,
     idx: RefCell<usize>,
     length: usize,
     pub do_shuffle: bool,
+    /// Positions of both sides for a single model, the side to move is encoded too
+    pub side_input: bool,
+    /// Evaluations are clamped to it, recorded in the model bundle
+    pub label_limit: f32,
 }
 
 impl SqliteChessDataloader {
@@ -41,6 +48,8 @@ impl SqliteChessDataloader {
             idx: RefCell::new(0),
             length: table_len,
             do_shuffle: false,
+            side_input: false,
+            label_limit: LABEL_LIMIT,
         }
     }
 }
@@ -78,22 +87,26 @@ impl DataLoader for SqliteChessDataloader {
             let fen: String = row.get_unwrap(0);
             let mut eval: f32 = row.get_unwrap(1);
 
-            // clamp (-20.0 | 20.0) eval
-            if eval > 20.0 {
-                eval = 20.0;
+            // clamp (-label_limit | label_limit) eval
+            if eval > self.label_limit {
+                eval = self.label_limit;
             }
 
-            if eval < -20.0 {
-                eval = -20.0;
+            if eval < -self.label_limit {
+                eval = -self.label_limit;
             }
 
             // minmax normalize eval
-            eval = (eval + 20.0) / 40.0;
+            eval = (eval + self.label_limit) / (2.0 * self.label_limit);
 
             let mut board = Board::from_fen(&fen)
                 .expect("[SqliteChessDataLoader] Failed to create board from fen");
 
-            v.push(encode_board(&mut board, eval).unwrap());
+            if self.side_input {
+                v.push(encode_board_with_side(&mut board, eval).unwrap());
+            } else {
+                v.push(encode_board(&mut board, eval).unwrap());
+            }
         }
 
         *self.idx.borrow_mut() += size;
@@ -0,0 +1,52 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/// Reads stdin of the UCI and XBoard front ends on a separate thread, so commands
+/// could be received while the search is running. Commands of `stop_cmds` also raise
+/// the stop flag, which aborts the running search without waiting for its iteration.
+/// Commands starting with one of `search_cmds` lower it in the order they were received,
+/// so a stop sent while idle doesn't abort the next search and one sent after it does.
+pub fn spawn_stdin_reader(
+    stop: Arc<AtomicBool>,
+    stop_cmds: &'static [&'static str],
+    search_cmds: &'static [&'static str],
+) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+
+            if stop_cmds.contains(&line.trim()) {
+                stop.store(true, Ordering::Relaxed);
+            } else if line
+                .split_whitespace()
+                .next()
+                .map_or(false, |cmd| search_cmds.contains(&cmd))
+            {
+                stop.store(false, Ordering::Relaxed);
+            }
+
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Writes a line to the GUI
+pub fn send(msg: &str) {
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", msg).expect("Failed to write to stdout");
+    stdout.flush().expect("Failed to flush stdout");
+}
@@ -0,0 +1,242 @@
+use log::{info, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use pleco::{Board, Player};
+
+pub const DEFAULT_EVAL_CACHE_MB: usize = 32;
+
+/// Second version stores centipawns from the side to move
+const FILE_MAGIC: &[u8; 4] = b"CTE2";
+const FILE_EXT: &str = "evalcache";
+
+/// Zobrist key and side to move
+type CacheKey = (u64, u8);
+
+/// Memoized network scores (in centipawns from the side to move) of already evaluated positions.
+/// Unlike the transposition table it doesn't depend on the search, so it's kept
+/// between moves and games and could be saved next to the model state.
+#[derive(Default)]
+pub struct EvalCache {
+    entries: HashMap<CacheKey, i16>,
+    capacity: usize,
+    /// Size the cache was created with, `--eval_cache`
+    size_mb: usize,
+    /// File the cache is saved to and loaded from
+    path: Option<PathBuf>,
+    /// Hash of the model state files, the saved cache is dropped when it doesn't match
+    model_hash: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn cache_key(board: &Board) -> CacheKey {
+    (board.zobrist(), board.turn() as u8)
+}
+
+/// FNV-1a over the model state files, stable between builds unlike `DefaultHasher`
+pub fn model_hash(paths: &[String]) -> Result<u64, Box<dyn Error>> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for p in paths {
+        for b in fs::read(p)? {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    Ok(hash)
+}
+
+impl EvalCache {
+    pub fn new(size_mb: usize) -> Self {
+        let entry_size = std::mem::size_of::<(CacheKey, i16)>() + 1;
+
+        Self {
+            entries: HashMap::new(),
+            capacity: size_mb * 1024 * 1024 / entry_size,
+            size_mb,
+            path: None,
+            model_hash: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Cache saved next to the first model state as `<state>.evalcache`, existing file
+    /// is loaded if it was produced by the same model states
+    pub fn persistent(size_mb: usize, states: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut cache = Self::new(size_mb);
+        let path = Path::new(&states[0]).with_extension(FILE_EXT);
+
+        cache.model_hash = model_hash(states)?;
+
+        if path.exists() {
+            match cache.load(&path) {
+                Ok(true) => info!(
+                    "Loaded {} cached evaluations from {}",
+                    cache.len(),
+                    path.display()
+                ),
+                Ok(false) => info!("Model changed, dropping eval cache {}", path.display()),
+                Err(e) => warn!("Failed to load eval cache {} : {}", path.display(), e),
+            }
+        }
+
+        cache.path = Some(path);
+
+        Ok(cache)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn size_mb(&self) -> usize {
+        self.size_mb
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Checks the position without touching the statistics
+    pub fn contains(&self, board: &Board) -> bool {
+        self.entries.contains_key(&cache_key(board))
+    }
+
+    pub fn probe(&mut self, board: &Board) -> Option<i16> {
+        let res = self.entries.get(&cache_key(board)).cloned();
+
+        if res.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+
+        res
+    }
+
+    pub fn store(&mut self, board: &Board, score: i16) {
+        self.store_key(board.zobrist(), board.turn(), score);
+    }
+
+    /// Same as `store` for a position which is no longer on the board
+    pub fn store_key(&mut self, key: u64, side: Player, score: i16) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        // no replacement scheme, the cache simply starts over when full
+        if self.entries.len() >= self.capacity {
+            self.entries.clear();
+        }
+
+        self.entries.insert((key, side as u8), score);
+    }
+
+    pub fn log_stats(&self) {
+        info!(
+            "Eval cache : {} entries, {} hits, {} misses, hit rate {:.1}%",
+            self.len(),
+            self.hits,
+            self.misses,
+            self.hit_rate()
+        );
+    }
+
+    /// Percentage of probes answered from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let probes = self.hits + self.misses;
+
+        if probes == 0 {
+            return 0.0;
+        }
+
+        self.hits as f64 * 100.0 / probes as f64
+    }
+
+    /// Returns false if the file belongs to another model
+    fn load(&mut self, path: &Path) -> Result<bool, Box<dyn Error>> {
+        let mut r = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+
+        if &magic != FILE_MAGIC {
+            return Err("not an eval cache file".into());
+        }
+
+        let mut buf8 = [0u8; 8];
+        r.read_exact(&mut buf8)?;
+
+        if u64::from_le_bytes(buf8) != self.model_hash {
+            return Ok(false);
+        }
+
+        r.read_exact(&mut buf8)?;
+        let cnt = (u64::from_le_bytes(buf8) as usize).min(self.capacity);
+
+        self.entries.reserve(cnt);
+
+        let mut entry = [0u8; 11];
+        for _ in 0..cnt {
+            r.read_exact(&mut entry)?;
+
+            let key = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            let score = i16::from_le_bytes(entry[9..11].try_into().unwrap());
+
+            self.entries.insert((key, entry[8]), score);
+        }
+
+        Ok(true)
+    }
+
+    /// Writes the cache to its file, does nothing for an in-memory cache.
+    /// It's called on drop, so the cache is saved however the session ends.
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        // write aside and rename, so an interrupted save doesn't corrupt the cache
+        let tmp_path = path.with_extension(format!("{}.tmp", FILE_EXT));
+
+        {
+            let mut w = BufWriter::new(File::create(&tmp_path)?);
+
+            w.write_all(FILE_MAGIC)?;
+            w.write_all(&self.model_hash.to_le_bytes())?;
+            w.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+
+            for ((key, side), score) in self.entries.iter() {
+                w.write_all(&key.to_le_bytes())?;
+                w.write_all(&[*side])?;
+                w.write_all(&score.to_le_bytes())?;
+            }
+
+            w.flush()?;
+        }
+
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for EvalCache {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("Failed to save eval cache : {}", e);
+        }
+    }
+}
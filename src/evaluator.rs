@@ -0,0 +1,106 @@
+use log::warn;
+use ndarray::Array1;
+use serde::Serialize;
+
+use nevermind_neu::models::*;
+use nevermind_neu::orchestra::*;
+
+use pleco::{Board, Player, ScoringMove};
+
+use crate::batch_eval::*;
+use crate::test::SearchCtx;
+use crate::train::Models;
+
+/// Leaf evaluation of the search, scores are in centipawns from the side to move.
+/// It picks the network of the side to move (or the single one for both sides),
+/// takes prefetched and cached scores and blends them with the handcrafted evaluation
+/// if `ctx.eval` asks for it.
+pub struct Evaluator<T: Model + Serialize + Clone> {
+    net: Orchestra<T>,
+    net_black: Orchestra<T>,
+    /// Batched evaluation of sibling leaves
+    pub leaf: LeafBatch<T>,
+}
+
+impl<T: Model + Serialize + Clone> Evaluator<T> {
+    pub fn new(models: Models<T>) -> Self {
+        Self {
+            leaf: LeafBatch::new(models.clone()),
+            net: Orchestra::new_for_eval(models.white).test_batch_size(1),
+            net_black: Orchestra::new_for_eval(models.black).test_batch_size(1),
+        }
+    }
+
+    /// Network input of the position
+    pub fn encode(&self, board: &mut Board) -> Array1<f32> {
+        self.leaf.encode(board)
+    }
+
+    /// Score of the position for its side to move
+    pub fn evaluate(&mut self, board: &mut Board, ctx: &mut SearchCtx) -> i16 {
+        if !ctx.eval.uses_net() {
+            return ctx.eval.blend(board, 0);
+        }
+
+        let score = match ctx.eval_cache.probe(board) {
+            Some(score) => score,
+            None => {
+                let score = match self.leaf.get(board.zobrist()) {
+                    Some(score) => score,
+                    None => self.eval_net(board),
+                };
+
+                ctx.eval_cache.store(board, score);
+                score
+            }
+        };
+
+        ctx.eval.blend(board, score)
+    }
+
+    /// Network score of a single position, the network of its side to move is used
+    fn eval_net(&mut self, board: &mut Board) -> i16 {
+        let input = self.encode(board);
+        self.eval_input(input, board.turn())
+    }
+
+    /// Network score of an encoded position with `side` to move
+    fn eval_input(&mut self, input: Array1<f32>, side: Player) -> i16 {
+        let net = match side {
+            Player::White => &mut self.net,
+            Player::Black => &mut self.net_black,
+        };
+
+        let out = net.eval_one(input).unwrap();
+        let out_b = out.borrow();
+
+        net_out_to_score(*out_b.first().unwrap(), side)
+    }
+
+    /// Scores positions after each of `moves` at once, see `LeafBatch::prefetch`
+    pub fn prefetch(&mut self, board: &mut Board, moves: &[ScoringMove], ctx: &SearchCtx) {
+        if ctx.eval.uses_net() {
+            self.leaf.prefetch(board, moves, &ctx.eval_cache);
+        }
+    }
+
+    /// Network scores of encoded positions with `side` to move, not blended.
+    /// They're evaluated one by one if the batch fails.
+    pub fn eval_batch(&mut self, inputs: &[Array1<f32>], side: Player) -> Vec<i16> {
+        match self.leaf.eval_batch(inputs, side) {
+            Ok(scores) => scores,
+            Err(e) => {
+                warn!("Batched evaluation failed, evaluating one by one : {}", e);
+                inputs
+                    .iter()
+                    .map(|inp| self.eval_input(inp.clone(), side))
+                    .collect()
+            }
+        }
+    }
+
+    /// Drops prefetched scores before a new search
+    pub fn clear(&mut self) {
+        self.leaf.clear();
+    }
+}
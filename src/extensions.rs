@@ -0,0 +1,79 @@
+use pleco::{BitMove, Board, PieceType, Player};
+
+pub const DEFAULT_EXTENSIONS: &str = "check,single_reply,recapture,pawn_7th";
+pub const DEFAULT_EXTENSION_BUDGET: u16 = 6;
+
+/// Search extensions, forcing moves are searched one ply deeper
+#[derive(Clone, Copy, Default)]
+pub struct Extensions {
+    /// Move gives check
+    pub check: bool,
+    /// The only legal move of the position
+    pub single_reply: bool,
+    /// Capture on the square of the previous capture
+    pub recapture: bool,
+    /// Pawn push to the 7th rank
+    pub pawn_7th: bool,
+    /// Maximum plies added along a single path from the root
+    pub budget: u16,
+}
+
+impl Extensions {
+    /// Parses a comma separated list like `DEFAULT_EXTENSIONS`, `none` disables all of them
+    pub fn parse(list: &str, budget: u16) -> Result<Self, String> {
+        let mut ext = Self {
+            budget,
+            ..Default::default()
+        };
+
+        for name in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match name {
+                "check" => ext.check = true,
+                "single_reply" => ext.single_reply = true,
+                "recapture" => ext.recapture = true,
+                "pawn_7th" => ext.pawn_7th = true,
+                "none" => {}
+                _ => return Err(format!("Unknown search extension : {}", name)),
+            }
+        }
+
+        Ok(ext)
+    }
+
+    pub fn all() -> Self {
+        Self::parse(DEFAULT_EXTENSIONS, DEFAULT_EXTENSION_BUDGET).unwrap()
+    }
+
+    /// Whether `mv` should be extended, checked before the move is applied
+    pub fn extends(&self, board: &Board, mv: BitMove, single_reply: bool) -> bool {
+        if self.single_reply && single_reply {
+            return true;
+        }
+
+        if self.check && board.gives_check(mv) {
+            return true;
+        }
+
+        if self.recapture && mv.is_capture() {
+            if let Some(prev) = board.last_move() {
+                if prev.is_capture() && prev.get_dest_u8() == mv.get_dest_u8() {
+                    return true;
+                }
+            }
+        }
+
+        if self.pawn_7th && board.moved_piece(mv).type_of() == PieceType::P {
+            let rank = mv.get_dest_u8() / 8;
+            let rank_7th = match board.turn() {
+                Player::White => 6,
+                Player::Black => 1,
+            };
+
+            if rank == rank_7th {
+                return true;
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,103 @@
+use pleco::tools::eval::Eval;
+use pleco::{Board, PieceType, Player};
+
+use crate::batch_eval::MAX_EVAL_V;
+
+pub const DEFAULT_NET_WEIGHT: f32 = 0.5;
+
+/// Minor pieces count 1, rooks 2 and queens 4 towards the game phase
+const MAX_PHASE: u32 = 24;
+
+/// Source of the leaf scores of `--eval`
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvalMode {
+    #[default]
+    Net,
+    /// pleco's handcrafted evaluation, a baseline to compare networks against
+    Classic,
+    /// Weighted sum of both
+    Hybrid,
+}
+
+/// How the network score is combined with the handcrafted one
+#[derive(Clone, Copy)]
+pub struct EvalBlend {
+    pub mode: EvalMode,
+    /// Weight of the network score with all pieces on the board
+    pub net_weight: f32,
+    /// Weight of the network score with pawns and kings only,
+    /// it's interpolated by the remaining material in between
+    pub net_weight_endgame: f32,
+}
+
+impl Default for EvalBlend {
+    fn default() -> Self {
+        Self {
+            mode: EvalMode::Net,
+            net_weight: DEFAULT_NET_WEIGHT,
+            net_weight_endgame: DEFAULT_NET_WEIGHT,
+        }
+    }
+}
+
+impl EvalBlend {
+    /// `weight_endgame` defaults to `weight`, so the blend doesn't depend on the phase
+    pub fn parse(mode: &str, weight: f32, weight_endgame: Option<f32>) -> Result<Self, String> {
+        let mode = match mode {
+            "net" => EvalMode::Net,
+            "classic" => EvalMode::Classic,
+            "hybrid" => EvalMode::Hybrid,
+            _ => return Err(format!("Unknown evaluation : {}", mode)),
+        };
+
+        let weight_endgame = weight_endgame.unwrap_or(weight);
+
+        if !(0.0..=1.0).contains(&weight) || !(0.0..=1.0).contains(&weight_endgame) {
+            return Err("Network weight must be in 0..1".to_string());
+        }
+
+        Ok(Self {
+            mode,
+            net_weight: weight,
+            net_weight_endgame: weight_endgame,
+        })
+    }
+
+    pub fn uses_net(&self) -> bool {
+        self.mode != EvalMode::Classic
+    }
+
+    /// Leaf score from the side to move, `net_score` is ignored by the classic mode
+    pub fn blend(&self, board: &Board, net_score: i16) -> i16 {
+        match self.mode {
+            EvalMode::Net => net_score,
+            EvalMode::Classic => classic_score(board),
+            EvalMode::Hybrid => {
+                let phase = phase(board);
+                let w = self.net_weight * phase + self.net_weight_endgame * (1.0 - phase);
+
+                (net_score as f32 * w + classic_score(board) as f32 * (1.0 - w)) as i16
+            }
+        }
+    }
+}
+
+/// Handcrafted evaluation in centipawns from the side to move,
+/// bounded like the network score
+pub fn classic_score(board: &Board) -> i16 {
+    Eval::eval_low(board).clamp(-MAX_EVAL_V as i32, MAX_EVAL_V as i32) as i16
+}
+
+/// 1.0 with all pieces on the board, 0.0 with pawns and kings only
+fn phase(board: &Board) -> f32 {
+    let mut phase = 0;
+
+    for player in [Player::White, Player::Black] {
+        phase += board.count_piece(player, PieceType::N) as u32
+            + board.count_piece(player, PieceType::B) as u32
+            + board.count_piece(player, PieceType::R) as u32 * 2
+            + board.count_piece(player, PieceType::Q) as u32 * 4;
+    }
+
+    phase.min(MAX_PHASE) as f32 / MAX_PHASE as f32
+}
@@ -5,13 +5,202 @@ use clap::{App, Arg, Command};
 
 use env_logger::Env;
 
+pub mod batch_eval;
+pub mod book;
+pub mod build_book;
+pub mod bundle;
 pub mod create_dataset;
 pub mod dataloader;
+pub mod engine_io;
+pub mod eval_cache;
+pub mod evaluator;
+pub mod extensions;
+pub mod hybrid_eval;
+pub mod mcts;
+pub mod model_cfg;
+pub mod ordering;
 pub mod play;
+pub mod pv;
+pub mod see;
+pub mod smp;
 pub mod sqlite_dataset;
+pub mod syzygy;
 pub mod test;
+pub mod timeman;
 pub mod train;
+pub mod tt;
+pub mod uci;
 pub mod util;
+pub mod xboard;
+
+/// Model states and their loading, shared by the commands playing with the networks
+fn model_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("ModelState")
+            .long("state")
+            .help("Single trained model for both sides, its input has the side to move")
+            .takes_value(true)
+            .conflicts_with_all(&["ModelStateWhite", "ModelStateBlack"]),
+        Arg::new("ModelStateWhite")
+            .long("state_white")
+            .help("Trained model for white's turn")
+            .takes_value(true)
+            .required_unless_present("ModelState"),
+        Arg::new("ModelStateBlack")
+            .long("state_black")
+            .help("Trained model for black's turn")
+            .takes_value(true)
+            .required_unless_present("ModelState"),
+        Arg::new("AllowBareState")
+            .long("allow_bare_state")
+            .help("Load model states without bundled description unchecked")
+            .takes_value(false),
+        Arg::new("ModelCfg")
+            .long("model_cfg")
+            .alias("solver_cfg")
+            .help("YAML or TOML file with the network architecture")
+            .takes_value(true)
+            .required(false),
+        Arg::new("Ocl")
+            .long("ocl")
+            .help("Use OpenCL computations")
+            .takes_value(false),
+    ]
+}
+
+/// Limits and options of the bot's search, shared by `test` and `play`
+fn search_args() -> Vec<Arg<'static>> {
+    vec![
+        Arg::new("Depth")
+            .long("depth")
+            .help("Max depth for search, 4 for test and 2 for play if no other limit is provided")
+            .takes_value(true)
+            .value_parser(clap::value_parser!(u16)),
+        Arg::new("MoveTime")
+            .long("movetime")
+            .help("Time for search in milliseconds")
+            .takes_value(true)
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("Nodes")
+            .long("nodes")
+            .help("Max number of nodes to search")
+            .takes_value(true)
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("Hash")
+            .long("hash")
+            .help("Transposition table size in MB")
+            .takes_value(true)
+            .default_value("16")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("Threads")
+            .long("threads")
+            .help("Number of search threads, each one has its own copy of the models")
+            .takes_value(true)
+            .default_value("1")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("MultiPv")
+            .long("multipv")
+            .help("Number of best root moves reported with their lines")
+            .takes_value(true)
+            .default_value("1")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("NoNullMove")
+            .long("no_null_move")
+            .help("Disable null move pruning")
+            .takes_value(false),
+        Arg::new("NoLmr")
+            .long("no_lmr")
+            .help("Disable late move reductions")
+            .takes_value(false),
+        Arg::new("NoFutility")
+            .long("no_futility")
+            .help("Disable futility pruning and razoring")
+            .takes_value(false),
+        Arg::new("NoPvs")
+            .long("no_pvs")
+            .help("Disable principal variation search")
+            .takes_value(false),
+        Arg::new("NoAspiration")
+            .long("no_aspiration")
+            .help("Disable aspiration windows")
+            .takes_value(false),
+        Arg::new("Extensions")
+            .long("extensions")
+            .help("Comma separated search extensions : check, single_reply, recapture, pawn_7th or none")
+            .takes_value(true)
+            .default_value("check,single_reply,recapture,pawn_7th"),
+        Arg::new("ExtensionBudget")
+            .long("extension_budget")
+            .help("Maximum plies added by extensions along a single line")
+            .takes_value(true)
+            .default_value("6")
+            .value_parser(clap::value_parser!(u16)),
+        Arg::new("Search")
+            .long("search")
+            .help("Search algorithm, alpha-beta or Monte Carlo tree search")
+            .takes_value(true)
+            .default_value("alphabeta")
+            .value_parser(["alphabeta", "mcts"]),
+        Arg::new("Playouts")
+            .long("playouts")
+            .help("Playouts per move of the mcts search without time or node limits")
+            .takes_value(true)
+            .default_value("800")
+            .value_parser(clap::value_parser!(u32)),
+        Arg::new("Cpuct")
+            .long("cpuct")
+            .help("Exploration constant of the mcts search")
+            .takes_value(true)
+            .default_value("1.5")
+            .value_parser(clap::value_parser!(f32)),
+        Arg::new("Book")
+            .long("book")
+            .help("Polyglot opening book (.bin)")
+            .takes_value(true),
+        Arg::new("BookDepth")
+            .long("book_depth")
+            .help("Plies from the start of the game the book is used for")
+            .takes_value(true)
+            .default_value("16")
+            .value_parser(clap::value_parser!(u16)),
+        Arg::new("SyzygyPath")
+            .long("syzygy_path")
+            .alias("syzygy-path")
+            .help("Directory with Syzygy tablebases, several ones are separated like in PATH")
+            .takes_value(true),
+        Arg::new("Eval")
+            .long("eval")
+            .help("Leaf evaluation of alpha-beta, network, pleco's handcrafted one or their blend")
+            .takes_value(true)
+            .default_value("net")
+            .value_parser(["net", "classic", "hybrid"]),
+        Arg::new("NetWeight")
+            .long("net_weight")
+            .help("Weight of the network score in the hybrid evaluation, 0..1")
+            .takes_value(true)
+            .default_value("0.5")
+            .value_parser(clap::value_parser!(f32)),
+        Arg::new("NetWeightEndgame")
+            .long("net_weight_endgame")
+            .help("Weight of the network score in pawn endgames, it's interpolated by the material from --net_weight")
+            .takes_value(true)
+            .value_parser(clap::value_parser!(f32)),
+        Arg::new("EvalCache")
+            .long("eval_cache")
+            .help("Network evaluation cache size in MB, 0 disables it")
+            .takes_value(true)
+            .default_value("32")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("PersistEvalCache")
+            .long("persist_eval_cache")
+            .help("Save evaluation cache next to the white model state and load it on start")
+            .takes_value(false),
+        Arg::new("QsEvasions")
+            .long("qs_evasions")
+            .help("Search all check evasions in quiescence search")
+            .takes_value(false),
+    ]
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
@@ -57,10 +246,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .takes_value(true)
                         .required(false),
                 )
+                .arg(
+                    Arg::new("SideToMove")
+                        .long("side_to_move")
+                        .help("Train a single model for both sides on positions of both of them")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("Side")
+                        .long("side")
+                        .help("Side to move of the dataset positions, recorded in the model bundle. Required unless --side_to_move is given or --state is a bundle")
+                        .takes_value(true)
+                        .value_parser(["white", "black"])
+                        .conflicts_with("SideToMove"),
+                )
+                .arg(
+                    Arg::new("AllowBareState")
+                        .long("allow_bare_state")
+                        .help("Load model states without bundled description unchecked")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("ModelCfg")
+                        .long("model_cfg")
+                        .help("YAML or TOML file with the network architecture")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::new("Out")
                         .long("out")
-                        .help("Specifies the model state filename")
+                        .help("Specifies the model bundle filename, snapshots are named after it")
                         .takes_value(true)
                         .default_value("chess_net"),
                 )
@@ -85,78 +300,56 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .subcommand(
             Command::new("test")
-                .arg(
-                    Arg::new("ModelStateWhite")
-                        .long("state_white")
-                        .help("Trained model for white's turn")
-                        .takes_value(true)
-                        .required(true),
-                )
-                .arg(
-                    Arg::new("ModelStateBlack")
-                        .long("state_black")
-                        .help("Trained model for black's turn")
-                        .takes_value(true)
-                        .required(true),
-                )
+                .args(model_args())
                 .arg(
                     Arg::new("Fen")
                         .long("fen")
                         .help("Provide fen for test")
                         .require_equals(true)
                         .takes_value(true)
-                        .required(true),
+                        .required_unless_present("Bench"),
                 )
                 .arg(
-                    Arg::new("ModelCfg")
-                        .long("solver_cfg")
-                        .help("Provide yaml solver cfg")
-                        .require_equals(true)
-                        .takes_value(true)
-                        .required(false),
+                    Arg::new("Bench")
+                        .long("bench")
+                        .help("Print node counts of fixed depth search over the benchmark positions")
+                        .takes_value(false),
                 )
+                .args(search_args())
                 .arg(
-                    Arg::new("Ocl")
-                        .long("ocl")
-                        .help("Enable OpenCL computations")
+                    Arg::new("Infinite")
+                        .long("infinite")
+                        .help("Analyze the position until Enter is pressed")
+                        .conflicts_with_all(&["Depth", "MoveTime", "Nodes"]),
+                )
+                .arg(
+                    Arg::new("BenchOrdering")
+                        .long("bench_ordering")
+                        .help("Print node counts of fixed depth search without and with move ordering")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("BenchBatch")
+                        .long("bench_batch")
+                        .help("Print timings of fixed depth search without and with batched leaf evaluation")
                         .takes_value(false),
                 ),
         )
         .about("Test trained model on FEN")
         .subcommand(
             Command::new("play")
-                .arg(
-                    Arg::new("ModelStateWhite")
-                        .long("state_white")
-                        .help("Trained model state file")
-                        .takes_value(true)
-                        .required(true),
-                )
-                .arg(
-                    Arg::new("ModelStateBlack")
-                        .long("state_black")
-                        .help("Trained model for black's turn")
-                        .takes_value(true)
-                        .required(true),
-                )
-                .arg(
-                    Arg::new("Ocl")
-                        .long("ocl")
-                        .help("Use OpenCL computations")
-                        .takes_value(false),
-                )
+                .args(model_args())
                 .arg(
                     Arg::new("Fen")
                         .long("fen")
                         .help("Display fen notation")
                 )
+                .args(search_args())
                 .arg(
-                    Arg::new("Depth")
-                        .long("depth")
-                        .help("Depth for bot's search. Use values from 1 to 4.")
-                        .takes_value(true)
-                        .value_parser(clap::value_parser!(u16))
-                        .default_value("2"),
+                    Arg::new("ShowPv")
+                        .long("show_pv")
+                        .help("Print the line the bot expects after its move")
+                        .takes_value(false),
                 )
                 .arg(
                     Arg::new("UnicodeDisplay")
@@ -193,6 +386,111 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ),
         )
         .about("LEGACY")
+        .subcommand(
+            Command::new("build_book")
+                .arg(
+                    Arg::new("Pgn")
+                        .long("pgn")
+                        .help("PGN file with games, could be given several times")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required_unless_present("DbPath"),
+                )
+                .arg(
+                    Arg::new("DbPath")
+                        .long("db")
+                        .help("Sqlite3 database with evaluated positions, could be given several times")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::new("Out")
+                        .long("out")
+                        .help("Polyglot book file to write")
+                        .takes_value(true)
+                        .default_value("book.bin"),
+                )
+                .arg(
+                    Arg::new("MinCount")
+                        .long("min_count")
+                        .help("Moves seen fewer times aren't added to the book")
+                        .takes_value(true)
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("MaxPly")
+                        .long("max_ply")
+                        .help("Moves deeper in the game aren't added to the book")
+                        .takes_value(true)
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(u16)),
+                )
+                .about("Build a polyglot opening book from games or evaluated positions"),
+        )
+        .subcommand(
+            Command::new("bundle")
+                .arg(
+                    Arg::new("State")
+                        .long("state")
+                        .help("Bare model state, a snapshot of train or a state of older versions")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("Side")
+                        .long("side")
+                        .help("Side to move the model evaluates, both for a single model with the side to move in its input")
+                        .takes_value(true)
+                        .value_parser(["white", "black", "both"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("ModelCfg")
+                        .long("model_cfg")
+                        .help("YAML or TOML file with the network architecture the state was trained with")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("Dataset")
+                        .long("dataset")
+                        .help("Dataset the state was trained on")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("EpochsNum")
+                        .long("epochs")
+                        .help("Number of epochs the state was trained for")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("LabelLimit")
+                        .long("label_limit")
+                        .help("Evaluations in pawns the state was trained on were clamped to it, 15 for dataset_from_db sets of older versions")
+                        .takes_value(true)
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(
+                    Arg::new("Out")
+                        .long("out")
+                        .help("Bundle filename")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .about("Pack a bare model state with its description into a self-describing bundle"),
+        )
+        .subcommand(
+            Command::new("uci")
+                .args(model_args())
+                .about("Run the bot as UCI engine on stdin/stdout"),
+        )
+        .subcommand(
+            Command::new("xboard")
+                .args(model_args())
+                .about("Run the bot as XBoard/CECP engine on stdin/stdout"),
+        )
         .get_matches();
 
     let (cmd, args) = matches.subcommand().unwrap();
@@ -213,6 +511,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         play::play_chess(&args)?;
     }
 
+    if cmd == "uci" {
+        if args.contains_id("Ocl") {
+            uci::uci_ocl(args)?;
+        } else {
+            uci::uci(args)?;
+        }
+    }
+
+    if cmd == "xboard" {
+        if args.contains_id("Ocl") {
+            xboard::xboard_ocl(args)?;
+        } else {
+            xboard::xboard(args)?;
+        }
+    }
+
+    if cmd == "build_book" {
+        build_book::build_book(args)?;
+    }
+
+    if cmd == "bundle" {
+        bundle::bundle(args)?;
+    }
+
     if cmd == "dataset_from_db" {
         sqlite_dataset::dataset_from_db(args)?;
     }
@@ -0,0 +1,400 @@
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use ndarray::Array1;
+use serde::Serialize;
+
+use nevermind_neu::models::*;
+
+use pleco::{BitMove, Board, Player, ScoringMove};
+
+use crate::batch_eval::*;
+use crate::evaluator::Evaluator;
+use crate::pv::PvLine;
+use crate::see::see;
+use crate::test::*;
+use crate::timeman::*;
+
+pub const DEFAULT_PLAYOUTS: u32 = 800;
+pub const DEFAULT_CPUCT: f32 = 1.5;
+
+/// Leaves selected before the network evaluates them as a single batch
+const MCTS_BATCH_SIZE: usize = 16;
+/// Visits counted as lost for each pending evaluation below a node,
+/// so leaves of the same batch spread over the tree
+const VIRTUAL_LOSS: u32 = 1;
+/// Unvisited children start with the value of their parent lowered by it
+const FPU_REDUCTION: f32 = 0.2;
+/// Softmax temperature of the move priors in centipawns
+const PRIOR_TEMPERATURE: f32 = 200.0;
+const CHECK_PRIOR_BONUS: f32 = 100.0;
+/// Playouts between the progress reports
+const REPORT_INTERVAL: u64 = 256;
+
+/// Options of `--search=mcts`
+#[derive(Clone, Copy)]
+pub struct MctsOptions {
+    /// Playouts per move, unless the search is limited by time or nodes
+    pub playouts: u32,
+    /// Exploration constant of PUCT
+    pub cpuct: f32,
+}
+
+impl Default for MctsOptions {
+    fn default() -> Self {
+        Self {
+            playouts: DEFAULT_PLAYOUTS,
+            cpuct: DEFAULT_CPUCT,
+        }
+    }
+}
+
+struct Node {
+    /// Move leading to the node, null for the root
+    mv: BitMove,
+    prior: f32,
+    visits: u32,
+    virtual_loss: u32,
+    /// Sum of playout values, each one from the side which played `mv`
+    value_sum: f32,
+    children: Vec<usize>,
+    expanded: bool,
+    /// Value for the side which played `mv` when the game is over in the node
+    terminal: Option<f32>,
+}
+
+impl Node {
+    fn new(mv: BitMove, prior: f32) -> Self {
+        Self {
+            mv,
+            prior,
+            visits: 0,
+            virtual_loss: 0,
+            value_sum: 0.0,
+            children: Vec::new(),
+            expanded: false,
+            terminal: None,
+        }
+    }
+
+    /// Average value for the side which played `mv`, pending evaluations count as losses
+    fn q(&self) -> Option<f32> {
+        let n = self.visits + self.virtual_loss;
+
+        if n == 0 {
+            None
+        } else {
+            Some(self.value_sum / n as f32)
+        }
+    }
+}
+
+/// Leaf waiting for the network
+struct Pending {
+    path: Vec<usize>,
+    key: u64,
+    side: Player,
+    input: Array1<f32>,
+    /// Leaf position, the network score is blended with its handcrafted one
+    board: Board,
+}
+
+/// Search tree stored as an arena, the root is the first node
+struct Tree {
+    nodes: Vec<Node>,
+    cpuct: f32,
+}
+
+impl Tree {
+    /// Adds children of the node. There is no policy network, so priors
+    /// come from SEE of captures and a bonus for checks.
+    fn expand(&mut self, idx: usize, board: &Board) {
+        let moves = board.generate_moves();
+
+        let weights: Vec<f32> = moves
+            .iter()
+            .map(|m| {
+                let mut h = 0.0;
+
+                if m.is_capture() || m.is_promo() {
+                    h += see(board, *m) as f32;
+                }
+                if board.gives_check(*m) {
+                    h += CHECK_PRIOR_BONUS;
+                }
+
+                (h / PRIOR_TEMPERATURE).exp()
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+
+        for (m, w) in moves.iter().zip(weights) {
+            self.nodes.push(Node::new(*m, w / total));
+
+            let child = self.nodes.len() - 1;
+            self.nodes[idx].children.push(child);
+        }
+
+        self.nodes[idx].expanded = true;
+    }
+
+    /// Child with the best PUCT score, values are from the side to move of `idx`
+    fn select_child(&self, idx: usize) -> usize {
+        let node = &self.nodes[idx];
+        let parent_n = (node.visits + node.virtual_loss).max(1) as f32;
+        // value of the node for its side to move, opposite to the one who played into it
+        let fpu = node.q().map(|q| 1.0 - q).unwrap_or(0.5) - FPU_REDUCTION;
+
+        let puct = |c: usize| {
+            let child = &self.nodes[c];
+            let n = (child.visits + child.virtual_loss) as f32;
+
+            child.q().unwrap_or(fpu) + self.cpuct * child.prior * parent_n.sqrt() / (1.0 + n)
+        };
+
+        *node
+            .children
+            .iter()
+            .max_by(|a, b| puct(**a).total_cmp(&puct(**b)))
+            .unwrap()
+    }
+
+    fn add_virtual_loss(&mut self, path: &[usize]) {
+        for i in path {
+            self.nodes[*i].virtual_loss += VIRTUAL_LOSS;
+        }
+    }
+
+    /// `value` is for the side which played into the leaf, it flips on the way up
+    fn backup(&mut self, path: &[usize], mut value: f32, virtual_loss: bool) {
+        for i in path.iter().rev() {
+            let node = &mut self.nodes[*i];
+
+            node.visits += 1;
+            node.value_sum += value;
+            if virtual_loss {
+                node.virtual_loss -= VIRTUAL_LOSS;
+            }
+
+            value = 1.0 - value;
+        }
+    }
+
+    fn most_visited(&self, idx: usize) -> Option<usize> {
+        self.nodes[idx]
+            .children
+            .iter()
+            .filter(|c| self.nodes[**c].visits > 0)
+            .max_by_key(|c| self.nodes[**c].visits)
+            .cloned()
+    }
+
+    /// Most visited line from the child `idx` of the root
+    fn line(&self, mut idx: usize) -> PvLine {
+        let score = value_to_score(self.nodes[idx].q().unwrap_or(0.5));
+        let mut moves = vec![self.nodes[idx].mv];
+
+        while let Some(c) = self.most_visited(idx) {
+            moves.push(self.nodes[c].mv);
+            idx = c;
+        }
+
+        PvLine { score, moves }
+    }
+}
+
+/// Value for the side which played into the position of a score for its side to move
+fn score_to_value(score: i16) -> f32 {
+    1.0 - score_to_net_out(score)
+}
+
+/// Centipawns of a value for the side which played the move, from its side
+fn value_to_score(value: f32) -> i16 {
+    net_out_to_score(value, Player::White)
+}
+
+/// PUCT Monte Carlo tree search driven by the value networks, their scores are blended
+/// like leaf scores of alpha-beta with `ctx.eval`.
+/// Leaves are collected with virtual loss and evaluated in batches. The search ends after
+/// `playouts`, or on the time and node limits (counted in playouts) when they're given.
+/// `on_iteration` gets progress reports with the most visited lines, it could return false to stop.
+pub fn mcts_search<T, F>(
+    board: &mut Board,
+    limits: &SearchLimits,
+    opts: MctsOptions,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
+    mut on_iteration: F,
+) -> ScoringMove
+where
+    T: Model + Serialize + Clone,
+    F: FnMut(&IterationInfo) -> bool,
+{
+    let start = Instant::now();
+    let budget = limits.budget();
+    let max_playouts = if limits.infinite || budget.is_some() || limits.nodes.is_some() {
+        limits.nodes
+    } else {
+        Some(opts.playouts as u64)
+    };
+
+    ctx.new_search();
+    ctx.history = game_history(board);
+    eval.clear();
+
+    let legal_moves = board.generate_moves();
+
+    if legal_moves.is_empty() {
+        return ScoringMove::blank(0);
+    }
+
+    if let Some(info) = tablebase_root(board, ctx, start) {
+        on_iteration(&info);
+        return ScoringMove::new_score(info.best_move, info.score);
+    }
+
+    let mut tree = Tree {
+        nodes: vec![Node::new(BitMove::null(), 1.0)],
+        cpuct: opts.cpuct,
+    };
+    tree.expand(0, board);
+
+    let mut next_report = REPORT_INTERVAL;
+
+    loop {
+        let mut pending: Vec<Pending> = Vec::with_capacity(MCTS_BATCH_SIZE);
+
+        for _ in 0..MCTS_BATCH_SIZE {
+            if max_playouts.map_or(false, |n| ctx.nodes + pending.len() as u64 >= n) {
+                break;
+            }
+
+            let mut path = vec![0];
+            let mut idx = 0;
+
+            // leaves waiting for the network are expanded, but not visited yet
+            while tree.nodes[idx].expanded
+                && tree.nodes[idx].terminal.is_none()
+                && (idx == 0 || tree.nodes[idx].visits > 0)
+            {
+                idx = tree.select_child(idx);
+
+                ctx.history.push(board.zobrist());
+                board.apply_move(tree.nodes[idx].mv);
+                path.push(idx);
+            }
+
+            let mut collision = false;
+
+            if tree.nodes[idx].terminal.is_none() {
+                if board.generate_moves().is_empty() {
+                    tree.nodes[idx].terminal = Some(if board.in_check() { 1.0 } else { 0.5 });
+                } else if is_draw(board, ctx) {
+                    tree.nodes[idx].terminal = Some(0.5);
+                } else if let Some(score) = ctx.syzygy.as_ref().and_then(|tb| tb.probe_wdl(board, 0)) {
+                    // tablebase score is for the side to move
+                    tree.nodes[idx].terminal = Some(0.5 - score.signum() as f32 * 0.5);
+                }
+            }
+
+            if let Some(value) = tree.nodes[idx].terminal {
+                tree.backup(&path, value, false);
+                ctx.nodes += 1;
+            } else if pending.iter().any(|p| p.path.last() == Some(&idx)) {
+                // the batch ran into a leaf it already waits for
+                collision = true;
+            } else if !ctx.eval.uses_net() {
+                tree.expand(idx, board);
+                tree.backup(&path, score_to_value(ctx.eval.blend(board, 0)), false);
+                ctx.nodes += 1;
+            } else if let Some(score) = ctx.eval_cache.probe(board) {
+                tree.expand(idx, board);
+                tree.backup(&path, score_to_value(ctx.eval.blend(board, score)), false);
+                ctx.nodes += 1;
+            } else {
+                tree.expand(idx, board);
+                tree.add_virtual_loss(&path);
+                pending.push(Pending {
+                    key: board.zobrist(),
+                    side: board.turn(),
+                    input: eval.encode(board),
+                    path: path.clone(),
+                    board: board.parallel_clone(),
+                });
+            }
+
+            for _ in 1..path.len() {
+                board.undo_move();
+                ctx.history.pop();
+            }
+
+            if collision {
+                break;
+            }
+        }
+
+        for side in [Player::White, Player::Black] {
+            let batch: Vec<&Pending> = pending.iter().filter(|p| p.side == side).collect();
+
+            if batch.is_empty() {
+                continue;
+            }
+
+            let inputs: Vec<Array1<f32>> = batch.iter().map(|p| p.input.clone()).collect();
+            let scores = eval.eval_batch(&inputs, side);
+
+            for (p, score) in batch.iter().zip(scores) {
+                ctx.eval_cache.store_key(p.key, p.side, score);
+                tree.backup(&p.path, score_to_value(ctx.eval.blend(&p.board, score)), true);
+                ctx.nodes += 1;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        let done = max_playouts.map_or(false, |n| ctx.nodes >= n)
+            || budget.map_or(false, |b| elapsed >= b.soft)
+            || ctx.stop.load(Ordering::Relaxed);
+
+        if done || ctx.nodes >= next_report {
+            next_report = ctx.nodes + REPORT_INTERVAL;
+
+            let mut root_children = tree.nodes[0].children.clone();
+            root_children.retain(|c| tree.nodes[*c].visits > 0);
+            root_children.sort_by_key(|c| std::cmp::Reverse(tree.nodes[*c].visits));
+
+            let lines: Vec<PvLine> = root_children
+                .iter()
+                .take(ctx.multipv.max(1))
+                .map(|c| tree.line(*c))
+                .collect();
+
+            if let Some(best) = lines.first() {
+                let info = IterationInfo {
+                    depth: best.moves.len() as u16,
+                    best_move: best.moves[0],
+                    score: best.score,
+                    lines: lines.clone(),
+                    nodes: ctx.nodes,
+                    qnodes: 0,
+                    elapsed,
+                };
+
+                if !on_iteration(&info) {
+                    break;
+                }
+            }
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    match tree.most_visited(0) {
+        Some(c) => ScoringMove::new_score(
+            tree.nodes[c].mv,
+            value_to_score(tree.nodes[c].q().unwrap_or(0.5)),
+        ),
+        None => ScoringMove::new_score(legal_moves[0], 0),
+    }
+}
@@ -0,0 +1,113 @@
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Activation function of a layer, named like in the config file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    #[default]
+    LeakyRelu,
+    Relu,
+    Sigmoid,
+    Tanh,
+    Raw,
+}
+
+/// Loss of the output layer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Loss {
+    #[default]
+    Euclidean,
+}
+
+/// Fully connected hidden layer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerCfg {
+    pub size: usize,
+    #[serde(default)]
+    pub activation: Activation,
+    /// Overrides the dropout of the model for this layer
+    #[serde(default)]
+    pub dropout: Option<f32>,
+}
+
+/// Network architecture of `--model_cfg`, input size follows from the board encoding.
+/// The output is a single value, search expects it in 0..1 like the sigmoid gives.
+///
+/// ```yaml
+/// layers:
+///   - { size: 900, activation: leaky_relu }
+///   - { size: 800, activation: leaky_relu, dropout: 0.2 }
+/// dropout: 0.13
+/// loss: euclidean
+/// output_activation: sigmoid
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelCfg {
+    pub layers: Vec<LayerCfg>,
+    /// Dropout of hidden layers while training
+    #[serde(default)]
+    pub dropout: f32,
+    #[serde(default)]
+    pub loss: Loss,
+    #[serde(default = "default_output_activation")]
+    pub output_activation: Activation,
+}
+
+fn default_output_activation() -> Activation {
+    Activation::Sigmoid
+}
+
+impl Default for ModelCfg {
+    /// Architecture the bundled networks were trained with
+    fn default() -> Self {
+        Self {
+            layers: (0..4)
+                .map(|i| LayerCfg {
+                    size: 900 - i * 100,
+                    activation: Activation::LeakyRelu,
+                    dropout: None,
+                })
+                .collect(),
+            dropout: 0.13,
+            loss: Loss::Euclidean,
+            output_activation: Activation::Sigmoid,
+        }
+    }
+}
+
+impl ModelCfg {
+    /// Reads a `.yaml`/`.yml` or `.toml` file
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+
+        let cfg: Self = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text)?,
+            Some("toml") => toml::from_str(&text)?,
+            _ => return Err(format!("Unknown model config format : {}", path).into()),
+        };
+
+        if cfg.layers.is_empty() || cfg.layers.iter().any(|l| l.size == 0) {
+            return Err(format!("Model config {} has an empty layer", path).into());
+        }
+
+        Ok(cfg)
+    }
+
+    /// Config of `--model_cfg` if it was given
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Self>, Box<dyn Error>> {
+        args.get_one::<String>("ModelCfg")
+            .map(|path| Self::open(path))
+            .transpose()
+    }
+
+    /// Dropout of the hidden layer `idx`
+    pub fn layer_dropout(&self, idx: usize) -> f32 {
+        self.layers[idx].dropout.unwrap_or(self.dropout)
+    }
+}
@@ -0,0 +1,140 @@
+use pleco::{BitMove, Board, PieceType, ScoringMove};
+
+use crate::see::see;
+
+pub const MAX_PLY: usize = 128;
+
+const HASH_MOVE_SCORE: i16 = 30000;
+const CAPTURE_SCORE: i16 = 20000;
+/// Captures losing material by SEE go after the quiet moves
+const BAD_CAPTURE_SCORE: i16 = -1000;
+const PROMOTION_SCORE: i16 = 19000;
+const KILLER_SCORE: [i16; 2] = [18000, 17000];
+/// Quiet moves are ordered by history below the killers
+const HISTORY_MAX: i32 = 16000;
+
+/// Heuristics used to search the most promising moves first,
+/// so alpha-beta gets its cutoffs earlier
+pub struct MoveOrdering {
+    /// Quiet moves which caused a beta cutoff on the same ply
+    killers: Vec<[BitMove; 2]>,
+    /// Cutoff statistics of quiet moves indexed by [from][to]
+    history: Vec<[i32; 64]>,
+}
+
+impl Default for MoveOrdering {
+    fn default() -> Self {
+        Self {
+            killers: vec![[BitMove::null(); 2]; MAX_PLY],
+            history: vec![[0; 64]; 64],
+        }
+    }
+}
+
+fn piece_order(p: PieceType) -> i16 {
+    match p {
+        PieceType::P => 1,
+        PieceType::N => 2,
+        PieceType::B => 3,
+        PieceType::R => 4,
+        PieceType::Q => 5,
+        PieceType::K => 6,
+        _ => 0,
+    }
+}
+
+/// Most valuable victim - least valuable attacker
+pub fn mvv_lva(board: &Board, mv: BitMove) -> i16 {
+    let victim = piece_order(board.captured_piece(mv));
+    let attacker = piece_order(board.moved_piece(mv).type_of());
+
+    victim * 10 - attacker
+}
+
+fn is_quiet(mv: BitMove) -> bool {
+    !mv.is_capture() && !mv.is_promo()
+}
+
+impl MoveOrdering {
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Killers belong to a single search, history is only scaled down
+    pub fn new_search(&mut self) {
+        for k in self.killers.iter_mut() {
+            *k = [BitMove::null(); 2];
+        }
+
+        for from in self.history.iter_mut() {
+            for h in from.iter_mut() {
+                *h /= 8;
+            }
+        }
+    }
+
+    fn score(&self, board: &Board, mv: BitMove, hash_move: Option<BitMove>, ply: usize) -> i16 {
+        if Some(mv) == hash_move {
+            return HASH_MOVE_SCORE;
+        }
+
+        if mv.is_capture() {
+            if see(board, mv) < 0 {
+                return BAD_CAPTURE_SCORE + mvv_lva(board, mv);
+            }
+
+            return CAPTURE_SCORE + mvv_lva(board, mv);
+        }
+
+        if mv.is_promo() {
+            return PROMOTION_SCORE + piece_order(mv.promo_piece());
+        }
+
+        if ply < MAX_PLY {
+            for (i, k) in self.killers[ply].iter().enumerate() {
+                if *k == mv {
+                    return KILLER_SCORE[i];
+                }
+            }
+        }
+
+        self.history[mv.get_src_u8() as usize][mv.get_dest_u8() as usize] as i16
+    }
+
+    /// Sorts moves in the order they should be searched
+    pub fn order_moves(
+        &self,
+        board: &Board,
+        moves: &mut [ScoringMove],
+        hash_move: Option<BitMove>,
+        ply: u16,
+    ) {
+        for m in moves.iter_mut() {
+            m.score = self.score(board, m.bit_move, hash_move, ply as usize);
+        }
+
+        moves.sort_by(|a, b| b.score.cmp(&a.score));
+    }
+
+    /// Sorts captures of the quiescence search by SEE, equal ones by MVV-LVA
+    pub fn order_captures(board: &Board, moves: &mut [BitMove]) {
+        moves.sort_by_cached_key(|m| (-see(board, *m), -mvv_lva(board, *m)));
+    }
+
+    /// Remembers a quiet move which caused a beta cutoff
+    pub fn update_cutoff(&mut self, mv: BitMove, ply: u16, depth: u16) {
+        if !is_quiet(mv) {
+            return;
+        }
+
+        let ply = ply as usize;
+
+        if ply < MAX_PLY && self.killers[ply][0] != mv {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = mv;
+        }
+
+        let h = &mut self.history[mv.get_src_u8() as usize][mv.get_dest_u8() as usize];
+        *h = (*h + depth as i32 * depth as i32).min(HISTORY_MAX);
+    }
+}
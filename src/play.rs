@@ -4,88 +4,59 @@ use serde::Serialize;
 use log::info;
 
 use clap::ArgMatches;
-use nevermind_neu::{models::*, orchestra::Orchestra};
+use nevermind_neu::models::*;
 use pleco::board::*;
 
 use rand::Rng;
 
-use pleco::{core::masks::SQ_DISPLAY_ORDER, SQ};
+use pleco::{core::masks::SQ_DISPLAY_ORDER, Player, SQ};
 
 use std::{error::Error, io};
 
+use crate::book::Book;
+use crate::evaluator::Evaluator;
+use crate::pv::PvLine;
+use crate::smp::SmpPool;
 use crate::test::*;
+use crate::timeman::*;
 use crate::train::*;
 
 pub fn play_chess(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let file_path_white = args.get_one::<String>("ModelStateWhite").unwrap();
-    let file_path_black = args.get_one::<String>("ModelStateBlack").unwrap();
     let is_ocl = args.contains_id("Ocl");
     let is_fen = args.contains_id("Fen");
     let unicode = args.contains_id("UnicodeDisplay");
-    let mut depth = args.get_one::<u16>("Depth").unwrap().clone();
-
-    if depth == 0 {
-        depth = 2;
-    }
+    let show_pv = args.contains_id("ShowPv");
+    let limits = SearchLimits::from_args(args, 2);
+    // transposition table is reused between bot's moves
+    let ctx = SearchCtx::from_args(args);
+    let book = Book::from_args(args)?;
 
     if is_ocl {
         info!("Using ocl...");
-        play_chess_ocl(
-            file_path_white.clone(),
-            file_path_black.clone(),
+        continue_play(
+            load_ocl_models(args)?,
             is_fen,
-            depth,
+            limits,
+            ctx,
             unicode,
+            show_pv,
+            book,
         )?;
     } else {
-        play_chess_cpu(
-            file_path_white.clone(),
-            file_path_black.clone(),
+        continue_play(
+            load_models(args)?,
             is_fen,
-            depth,
+            limits,
+            ctx,
             unicode,
+            show_pv,
+            book,
         )?;
     }
 
     Ok(())
 }
 
-fn play_chess_ocl(
-    model_state_white: String,
-    model_state_black: String,
-    display_fen: bool,
-    d: u16,
-    unicode: bool,
-) -> Result<(), Box<dyn Error>> {
-    let mut mdl_white = SequentialOcl::new()?;
-    fill_ocl_model_with_layers(&mut mdl_white, false);
-    mdl_white.load_state(&model_state_white)?;
-
-    let mut mdl_black = SequentialOcl::new()?;
-    fill_ocl_model_with_layers(&mut mdl_black, false);
-    mdl_black.load_state(&model_state_black)?;
-
-    continue_play(mdl_white, mdl_black, display_fen, d, unicode)
-}
-
-fn play_chess_cpu(
-    model_state_white: String,
-    model_state_black: String,
-    display_fen: bool,
-    d: u16,
-    unicode: bool,
-) -> Result<(), Box<dyn Error>> {
-    let mut mdl_white = Sequential::new();
-    fill_model_with_layers(&mut mdl_white, false);
-    mdl_white.load_state(&model_state_white)?;
-
-    let mut mdl_black = Sequential::new();
-    fill_model_with_layers(&mut mdl_black, false);
-    mdl_black.load_state(&model_state_black)?;
-
-    continue_play(mdl_white, mdl_black, display_fen, d, unicode)
-}
-
 fn read_string_from_stdin(stdin: &io::Stdin) -> Result<String, Box<dyn Error>> {
     let mut buf_str = String::new();
     stdin.read_line(&mut buf_str)?;
@@ -109,16 +80,18 @@ impl Turn {
     }
 }
 
-fn continue_play<T: Model + Serialize + Clone>(
-    mdl_white: T,
-    mdl_black: T,
+fn continue_play<T: Model + Serialize + Clone + Send + 'static>(
+    models: Models<T>,
     display_fen: bool,
-    d: u16,
+    limits: SearchLimits,
+    mut ctx: SearchCtx,
     unicode: bool,
+    show_pv: bool,
+    book: Option<Book>,
 ) -> Result<(), Box<dyn Error>> {
     // initialize orchestra
-    let mut orc_white = Orchestra::new_for_eval(mdl_white).test_batch_size(1);
-    let mut orc_black = Orchestra::new_for_eval(mdl_black).test_batch_size(1);
+    let mut pool = SmpPool::new(&models, &ctx);
+    let mut eval = Evaluator::new(models);
 
     let stdin = io::stdin();
 
@@ -138,7 +111,11 @@ fn continue_play<T: Model + Serialize + Clone>(
 
     println!("UCI Move examples : 'e2e4', 'e7e8q' - pawn to queen promotes");
 
-    while !board.checkmate() {
+    let end = loop {
+        if let Some(end) = game_end(&board) {
+            break end;
+        }
+
         println!("==== Move {} ====", board.moves_played());
 
         if display_fen {
@@ -151,13 +128,32 @@ fn continue_play<T: Model + Serialize + Clone>(
             do_player_step(&stdin, &mut board)?;
         } else {
             println!("Bot is thinking...");
-            do_bot_step(&mut board, &mut orc_white, &mut orc_black, d)?;
+            let line = do_bot_step(
+                &mut board,
+                &mut eval,
+                &mut pool,
+                &limits,
+                &mut ctx,
+                book.as_ref(),
+            )?;
+
+            if let (true, Some(line)) = (show_pv, line) {
+                println!("Expected line : {}", line.moves_str());
+            }
         }
 
         turn.switch();
-    }
+    };
+
+    print_board(&mut board, unicode);
 
-    println!("Checkmate!");
+    if end.is_draw() {
+        println!("{}, 1/2-1/2", end.describe());
+    } else if board.turn() == Player::White {
+        println!("Checkmate! Black wins");
+    } else {
+        println!("Checkmate! White wins");
+    }
 
     Ok(())
 }
@@ -178,36 +174,45 @@ fn do_player_step(io: &io::Stdin, b: &mut Board) -> Result<(), Box<dyn Error>> {
 
 fn do_bot_step<T: Model + Serialize + Clone>(
     b: &mut Board,
-    orc: &mut Orchestra<T>,
-    orc_black: &mut Orchestra<T>,
-    depth: u16,
-) -> Result<(), Box<dyn Error>> {
-    if b.moves_played() < 2 {
+    eval: &mut Evaluator<T>,
+    pool: &mut SmpPool,
+    limits: &SearchLimits,
+    ctx: &mut SearchCtx,
+    book: Option<&Book>,
+) -> Result<Option<PvLine>, Box<dyn Error>> {
+    let mut line = None;
+
+    if let Some(book_move) = book.and_then(|bk| bk.pick(b)) {
+        println!("Book move");
+        b.apply_move(book_move);
+    } else if book.is_none() && b.moves_played() < 2 {
         // first move is random
         let rand_moves = b.generate_moves();
         let mut rng = rand::thread_rng();
         b.apply_move(rand_moves[rng.gen_range(0..rand_moves.len()) as usize]);
-    } else if b.moves_played() < 4 {
-        let is_inv = if b.turn() == pleco::Player::White {
-            depth % 2 == 1
-        } else {
-            depth % 2 == 0
-        };
-        let best_move = my_alpha_beta_search(b, -15000, 15000, 2, orc, orc_black, is_inv);
-        b.apply_move(best_move.bit_move);
+    } else if book.is_none() && b.moves_played() < 4 {
+        let best_move = pool.search(b, &SearchLimits::with_depth(2), eval, ctx, |it| {
+            line = it.lines.first().cloned();
+            true
+        });
+        b.apply_move(playable_move(b, best_move.bit_move).ok_or("No legal move to play")?);
     } else {
-        let is_inv = if b.turn() == pleco::Player::White {
-            depth % 2 == 1
-        } else {
-            depth % 2 == 0
-        };
-        let best_move = my_alpha_beta_search(b, -16000, 16000, depth, orc, orc_black, is_inv);
-        b.apply_move(best_move.bit_move);
+        let best_move = pool.search(b, limits, eval, ctx, |it| {
+            info!(
+                "Depth {} : {} - {}, nodes {}",
+                it.depth, it.best_move, it.score, it.nodes
+            );
+            line = it.lines.first().cloned();
+            true
+        });
+        b.apply_move(playable_move(b, best_move.bit_move).ok_or("No legal move to play")?);
+
+        ctx.eval_cache.log_stats();
     }
 
     println!("Bot's move : {}", b.last_move().unwrap());
 
-    Ok(())
+    Ok(line)
 }
 
 fn print_board(b: &mut Board, unicode: bool) {
@@ -0,0 +1,66 @@
+use pleco::BitMove;
+
+use crate::ordering::MAX_PLY;
+
+/// Line the search expects from a root move
+#[derive(Clone)]
+pub struct PvLine {
+    pub score: i16,
+    pub moves: Vec<BitMove>,
+}
+
+impl PvLine {
+    /// Moves in uci notation separated by spaces
+    pub fn moves_str(&self) -> String {
+        self.moves
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Triangular table of principal variations, line of each ply
+/// is the best move followed by the line of the next ply
+pub struct PvTable {
+    lines: Vec<Vec<BitMove>>,
+}
+
+impl Default for PvTable {
+    fn default() -> Self {
+        Self {
+            lines: vec![Vec::new(); MAX_PLY + 1],
+        }
+    }
+}
+
+impl PvTable {
+    /// Must be called when the node is entered, so lines of previous siblings are dropped
+    pub fn clear(&mut self, ply: u16) {
+        if let Some(l) = self.lines.get_mut(ply as usize) {
+            l.clear();
+        }
+    }
+
+    /// `mv` became the best move of the node at `ply`
+    pub fn update(&mut self, ply: u16, mv: BitMove) {
+        let ply = ply as usize;
+
+        if ply >= MAX_PLY {
+            return;
+        }
+
+        let (head, tail) = self.lines.split_at_mut(ply + 1);
+
+        head[ply].clear();
+        head[ply].push(mv);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+
+    pub fn line(&self, ply: u16) -> &[BitMove] {
+        self.lines
+            .get(ply as usize)
+            .map(|l| l.as_slice())
+            .unwrap_or(&[])
+    }
+}
@@ -0,0 +1,101 @@
+use pleco::{BitBoard, BitMove, Board, PieceType, Player, SQ};
+
+/// Material values of the exchange in centipawns, the king can't be traded
+fn piece_value(p: PieceType) -> i32 {
+    match p {
+        PieceType::P => 100,
+        PieceType::N => 320,
+        PieceType::B => 330,
+        PieceType::R => 500,
+        PieceType::Q => 900,
+        PieceType::K => 20000,
+        _ => 0,
+    }
+}
+
+/// Least valuable piece of `side` among `attackers`
+fn least_valuable(
+    board: &Board,
+    attackers: BitBoard,
+    side: Player,
+) -> Option<(BitBoard, PieceType)> {
+    for pt in [
+        PieceType::P,
+        PieceType::N,
+        PieceType::B,
+        PieceType::R,
+        PieceType::Q,
+        PieceType::K,
+    ] {
+        let bb = attackers & board.piece_bb(side, pt);
+
+        if !bb.is_empty() {
+            return Some((bb.bit_scan_forward().to_bb(), pt));
+        }
+    }
+
+    None
+}
+
+/// Static exchange evaluation : material won by the side to move in centipawns
+/// after `mv` and the best sequence of recaptures on its destination square.
+/// Sliders behind the capturing pieces join the exchange as the occupancy shrinks.
+pub fn see(board: &Board, mv: BitMove) -> i32 {
+    let to = mv.get_dest();
+    let mut side = board.turn();
+
+    let mut gain = [0i32; 32];
+    let mut d = 0;
+
+    let mut occ = board.occupied();
+    let mut from_bb = mv.get_src().to_bb();
+    // piece standing on the destination after the capture
+    let mut piece = board.piece_at_sq(mv.get_src()).type_of();
+
+    if mv.is_en_passant() {
+        let captured_sq = match side {
+            Player::White => SQ(to.0 - 8),
+            Player::Black => SQ(to.0 + 8),
+        };
+
+        occ = occ ^ captured_sq.to_bb();
+        gain[0] = piece_value(PieceType::P);
+    } else if mv.is_capture() {
+        gain[0] = piece_value(board.piece_at_sq(to).type_of());
+    }
+
+    if mv.is_promo() {
+        piece = mv.promo_piece();
+        gain[0] += piece_value(piece) - piece_value(PieceType::P);
+    }
+
+    loop {
+        d += 1;
+        gain[d] = piece_value(piece) - gain[d - 1];
+
+        // neither side could gain by continuing
+        if (-gain[d - 1]).max(gain[d]) < 0 || d + 1 >= gain.len() {
+            break;
+        }
+
+        occ = occ ^ from_bb;
+        side = side.other_player();
+
+        let attackers = board.attackers_to(to, occ) & occ & board.get_occupied_player(side);
+
+        match least_valuable(board, attackers, side) {
+            Some((bb, pt)) => {
+                from_bb = bb;
+                piece = pt;
+            }
+            None => break,
+        }
+    }
+
+    while d > 1 {
+        d -= 1;
+        gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+    }
+
+    gain[0]
+}
@@ -0,0 +1,189 @@
+use log::debug;
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use nevermind_neu::models::*;
+
+use pleco::{Board, ScoringMove};
+
+use crate::evaluator::Evaluator;
+use crate::mcts::mcts_search;
+use crate::test::*;
+use crate::timeman::*;
+use crate::train::Models;
+use crate::tt::TranspositionTable;
+
+struct Job {
+    board: Board,
+    limits: SearchLimits,
+    tt: Arc<TranspositionTable>,
+    /// Raised once the main search of this job ends
+    stop: Arc<AtomicBool>,
+}
+
+/// Deepest iteration completed by a helper
+struct HelperResult {
+    depth: u16,
+    best_move: Option<ScoringMove>,
+    nodes: u64,
+}
+
+struct Worker {
+    jobs: Sender<Job>,
+    handle: JoinHandle<()>,
+}
+
+/// Helper threads of the Lazy SMP search.
+/// Every helper owns networks built from its own copy of the models and searches
+/// the same position as the main thread, they only cooperate through the shared
+/// transposition table. Orchestras aren't shared, so they're created inside the threads.
+pub struct SmpPool {
+    workers: Vec<Worker>,
+    results: Receiver<HelperResult>,
+    /// Nodes searched by the helpers during the last search
+    pub helper_nodes: u64,
+}
+
+impl SmpPool {
+    /// Spawns `ctx.threads - 1` helpers, options of the search are taken from `ctx`
+    pub fn new<T: Model + Serialize + Clone + Send + 'static>(
+        models: &Models<T>,
+        ctx: &SearchCtx,
+    ) -> Self {
+        let (results_tx, results) = mpsc::channel();
+
+        let workers = (1..ctx.threads.max(1))
+            .map(|id| spawn_worker(models.clone(), ctx.helper(id), results_tx.clone()))
+            .collect();
+
+        Self {
+            workers,
+            results,
+            helper_nodes: 0,
+        }
+    }
+
+    /// `iterative_deepening` on the calling thread with the helpers searching alongside.
+    /// Helpers are stopped once the main search completes, the result of the deepest
+    /// completed iteration among all threads is returned. Raising `ctx.stop` stops the main
+    /// search and so all of them, the flag itself is left to its owner.
+    /// Monte Carlo tree search runs on the calling thread only.
+    pub fn search<T, F>(
+        &mut self,
+        board: &mut Board,
+        limits: &SearchLimits,
+        eval: &mut Evaluator<T>,
+        ctx: &mut SearchCtx,
+        mut on_iteration: F,
+    ) -> ScoringMove
+    where
+        T: Model + Serialize + Clone,
+        F: FnMut(&IterationInfo) -> bool,
+    {
+        self.helper_nodes = 0;
+
+        if let Some(opts) = ctx.mcts {
+            return mcts_search(board, limits, opts, eval, ctx, on_iteration);
+        }
+
+        if self.workers.is_empty() {
+            return iterative_deepening(board, limits, eval, ctx, on_iteration);
+        }
+
+        // helpers get a flag of their own, so the one of the caller is never lowered here
+        let helper_stop = Arc::new(AtomicBool::new(false));
+
+        // time and node limits are checked by the main thread only
+        let helper_limits = SearchLimits::with_depth(limits.max_depth());
+
+        for w in self.workers.iter() {
+            let job = Job {
+                board: board.parallel_clone(),
+                limits: helper_limits.clone(),
+                tt: ctx.tt.clone(),
+                stop: helper_stop.clone(),
+            };
+
+            w.jobs.send(job).expect("Lazy SMP helper thread is gone");
+        }
+
+        let mut depth = 0;
+        let mut best_move = iterative_deepening(board, limits, eval, ctx, |it| {
+            depth = it.depth;
+            on_iteration(it)
+        });
+
+        helper_stop.store(true, Ordering::Relaxed);
+
+        for _ in 0..self.workers.len() {
+            let res = self.results.recv().expect("Lazy SMP helper thread is gone");
+
+            self.helper_nodes += res.nodes;
+
+            if let Some(m) = res.best_move {
+                if res.depth > depth {
+                    debug!(
+                        "Helper result of depth {} is used : {}",
+                        res.depth, m.bit_move
+                    );
+                    depth = res.depth;
+                    best_move = m;
+                }
+            }
+        }
+
+        best_move
+    }
+}
+
+impl Drop for SmpPool {
+    fn drop(&mut self) {
+        for w in self.workers.drain(..) {
+            // closed job channel ends the worker loop
+            drop(w.jobs);
+            let _ = w.handle.join();
+        }
+    }
+}
+
+fn spawn_worker<T: Model + Serialize + Clone + Send + 'static>(
+    models: Models<T>,
+    mut ctx: SearchCtx,
+    results: Sender<HelperResult>,
+) -> Worker {
+    let (jobs, jobs_rx) = mpsc::channel::<Job>();
+
+    let handle = thread::spawn(move || {
+        let mut eval = Evaluator::new(models);
+
+        for job in jobs_rx {
+            let mut board = job.board;
+            ctx.tt = job.tt;
+            ctx.stop = job.stop;
+
+            let mut res = HelperResult {
+                depth: 0,
+                best_move: None,
+                nodes: 0,
+            };
+
+            iterative_deepening(&mut board, &job.limits, &mut eval, &mut ctx, |it| {
+                res.depth = it.depth;
+                res.best_move = Some(ScoringMove::new_score(it.best_move, it.score));
+                true
+            });
+
+            res.nodes = ctx.total_nodes();
+
+            if results.send(res).is_err() {
+                break;
+            }
+        }
+    });
+
+    Worker { jobs, handle }
+}
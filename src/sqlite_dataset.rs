@@ -10,9 +10,15 @@ use ndarray::Array;
 
 use log::{debug, error, info};
 
+use crate::dataloader::LABEL_LIMIT;
 use crate::util;
 
-const SCORE_LIMIT: f32 = 15.0;
+/// Input size of the models trained for a single side to move
+pub const INPUT_SIZE: usize = 898; // 8 * 8 * 14 + 2
+/// Input size of a single model for both sides, the side to move is encoded as two more values
+pub const INPUT_SIZE_WITH_SIDE: usize = INPUT_SIZE + 2;
+/// Bumped whenever `encode_board` changes, models of other versions are refused
+pub const ENCODING_VERSION: u32 = 1;
 
 pub fn dataset_from_db(args: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
     let db_path = args.get_one::<String>("DbPath").unwrap();
@@ -33,15 +39,18 @@ pub fn dataset_from_db(args: &ArgMatches) -> Result<(), Box<dyn std::error::Erro
         let fen:String = row.get_unwrap(1);
         let mut eval:f64 = row.get_unwrap(3);
 
-        if eval > SCORE_LIMIT as f64 {
-            eval = SCORE_LIMIT as f64;
+        // same scaling as `SqliteChessDataloader`, which models are checked against
+        let limit = LABEL_LIMIT as f64;
+
+        if eval > limit {
+            eval = limit;
         }
 
-        if eval < -SCORE_LIMIT as f64 {
-            eval = -SCORE_LIMIT as f64;
+        if eval < -limit {
+            eval = -limit;
         }
 
-        eval = (eval + 15.0) / 30.0;
+        eval = (eval + limit) / (2.0 * limit);
 
         let board_opt = Board::from_fen(fen.as_str());
 
@@ -94,6 +103,15 @@ fn piece_to_type(p: &Piece) -> usize {
 }
 
 pub fn encode_board(b: &mut Board, eval: f32) -> Option<LabeledEntry> {
+    encode(b, eval, false)
+}
+
+/// Same as `encode_board` with the side to move at the end of the input
+pub fn encode_board_with_side(b: &mut Board, eval: f32) -> Option<LabeledEntry> {
+    encode(b, eval, true)
+}
+
+fn encode(b: &mut Board, eval: f32, with_side: bool) -> Option<LabeledEntry> {
     let mut db = LabeledEntry::default();
 
     let mut inp_vec = Vec::with_capacity(8 * 8 * 12);
@@ -186,15 +204,15 @@ pub fn encode_board(b: &mut Board, eval: f32) -> Option<LabeledEntry> {
         }
     }
 
-    /*
-    if b.turn() == Player::White {
-        inp_vec.push(1.0);
-        inp_vec.push(0.0);
-    } else {
-        inp_vec.push(0.0);
-        inp_vec.push(1.0);
+    if with_side {
+        if b.turn() == Player::White {
+            inp_vec.push(1.0);
+            inp_vec.push(0.0);
+        } else {
+            inp_vec.push(0.0);
+            inp_vec.push(1.0);
+        }
     }
-    */
 
     let arr = Array::from_shape_vec(inp_vec.len(), inp_vec);
 
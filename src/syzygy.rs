@@ -0,0 +1,109 @@
+use log::info;
+
+use std::env;
+use std::error::Error;
+
+use pleco::{BitMove, Board};
+
+use crate::ordering::MAX_PLY;
+
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess};
+use shakmaty_syzygy::{Tablebase, Wdl};
+
+/// Score of a tablebase win at the root, above any network score and below mate scores,
+/// so the search still prefers a real mate. Like mates it's lowered by the ply of the win.
+pub const TB_WIN_V: i16 = 20000;
+/// Scores beyond it are tablebase wins or mates, they're stored relative to the node in the TT
+pub const TB_BOUND: i16 = TB_WIN_V - MAX_PLY as i16;
+const DRAW_V: i16 = 0;
+
+/// Moves without captures or pawn pushes before the game is drawn
+const RULE_50_PLIES: i32 = 100;
+
+/// Syzygy WDL (.rtbw) and DTZ (.rtbz) tables of positions with few pieces
+pub struct Syzygy {
+    tables: Tablebase<Chess>,
+}
+
+impl Syzygy {
+    /// `path` is a directory or several of them separated like the PATH variable
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut tables = Tablebase::new();
+        let mut cnt = 0;
+
+        for dir in env::split_paths(path) {
+            cnt += tables.add_directory(&dir)?;
+        }
+
+        if cnt == 0 {
+            return Err(format!("No syzygy tables found in {}", path).into());
+        }
+
+        info!(
+            "{} syzygy tables loaded, up to {} pieces",
+            cnt,
+            tables.max_pieces()
+        );
+
+        Ok(Self { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    /// Position of the board if it could be in the tables
+    fn position(&self, board: &Board) -> Option<Chess> {
+        if board.occupied().count_bits() as usize > self.max_pieces() {
+            return None;
+        }
+
+        let fen: Fen = board.fen().parse().ok()?;
+        fen.into_position(CastlingMode::Standard).ok()
+    }
+
+    /// Exact score for the side to move at `ply`, so nearer wins score higher. Tables with
+    /// castling rights or missing tables give None. WDL tables assume the 50 move counter
+    /// was just reset, so positions are probed only after captures and pawn moves.
+    pub fn probe_wdl(&self, board: &Board, ply: u16) -> Option<i16> {
+        if board.rule_50() != 0 {
+            return None;
+        }
+
+        let pos = self.position(board)?;
+
+        match self.tables.probe_wdl_after_zeroing(&pos).ok()? {
+            Wdl::Win => Some(TB_WIN_V - ply as i16),
+            Wdl::Loss => Some(-TB_WIN_V + ply as i16),
+            // cursed wins and blessed losses are drawn by the 50 move rule
+            _ => Some(DRAW_V),
+        }
+    }
+
+    /// DTZ-optimal move and its score at the root, the move keeps the win
+    /// within the 50 move rule if there is one
+    pub fn probe_root(&self, board: &Board) -> Option<(BitMove, i16)> {
+        let pos = self.position(board)?;
+
+        let (mv, _) = self.tables.best_move(&pos).ok()??;
+        let dtz = self.tables.probe_dtz(&pos).ok()?.ignore_rounding().0;
+        let uci = mv.to_uci(CastlingMode::Standard).to_string();
+        let mv = board
+            .generate_moves()
+            .iter()
+            .find(|m| m.to_string() == uci)
+            .cloned()?;
+
+        let rule_50 = board.rule_50() as i32;
+        let score = if dtz > 0 && dtz + rule_50 <= RULE_50_PLIES {
+            TB_WIN_V
+        } else if dtz < 0 && -dtz + rule_50 <= RULE_50_PLIES {
+            -TB_WIN_V
+        } else {
+            DRAW_V
+        };
+
+        Some((mv, score))
+    }
+}
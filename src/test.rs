@@ -1,136 +1,855 @@
 use clap::ArgMatches;
-use log::info;
+use log::{info, warn};
 
 use serde::Serialize;
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use nevermind_neu::models::*;
 
 use pleco::Board;
 use pleco::*;
 
-use nevermind_neu::orchestra::*;
-
+use crate::book::Book;
+use crate::eval_cache::*;
+use crate::evaluator::Evaluator;
+use crate::extensions::*;
+use crate::hybrid_eval::EvalBlend;
+use crate::mcts::MctsOptions;
+use crate::ordering::*;
+use crate::pv::*;
+use crate::see::see;
+use crate::smp::SmpPool;
 use crate::sqlite_dataset::*;
+use crate::syzygy::{Syzygy, TB_BOUND};
+use crate::timeman::*;
 use crate::train::*;
+use crate::tt::*;
 
 const MATE_V: i16 = 31000 as i16;
 const DRAW_V: i16 = 0 as i16;
+/// Bigger than any score, so the root window never cuts off a mate
+const INF_V: i16 = 32000;
+/// Scores beyond it are mates in at most `MAX_PLY` plies, network scores stay below `MAX_EVAL_V`
+pub const MATE_BOUND: i16 = MATE_V - MAX_PLY as i16;
 
-pub fn test(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let state_white = args.get_one::<String>("ModelStateWhite").unwrap();
-    let state_black = args.get_one::<String>("ModelStateBlack").unwrap();
+/// Safeguard against long capture sequences in the quiescence search
+const MAX_QSEARCH_PLY: u16 = 8;
+
+/// Scores of the search are in centipawns from the side to move
+const PAWN_V: i16 = 100;
 
-    let mut mdl_white = Sequential::new();
-    let mut mdl_black = Sequential::new();
+const NULL_MOVE_MIN_DEPTH: u16 = 3;
+const LMR_MIN_DEPTH: u16 = 3;
+/// Moves searched at full depth before the reductions start
+const LMR_FULL_MOVES: usize = 3;
+const FUTILITY_MAX_DEPTH: u16 = 3;
+const FUTILITY_MARGIN: i16 = PAWN_V * 3 / 2;
+const RAZOR_MAX_DEPTH: u16 = 2;
+const RAZOR_MARGIN: i16 = PAWN_V * 3;
 
-    fill_model_with_layers(&mut mdl_white, false);
-    fill_model_with_layers(&mut mdl_black, false);
+/// Clock and stop signal are looked at once per this many nodes, node limit on every node
+const LIMITS_CHECK_INTERVAL: u64 = 128;
 
-    mdl_white.load_state(state_white)?;
-    mdl_black.load_state(state_black)?;
+const ASPIRATION_MIN_DEPTH: u16 = 4;
+/// Initial half width of the aspiration window, doubled on each fail
+const ASPIRATION_WINDOW: i16 = PAWN_V / 2;
 
-    continue_test(args, mdl_white, mdl_black)
+/// Positions of `test --bench`, node counts over them compare search changes
+const BENCH_FENS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+    "8/8/8/4k3/8/8/3QK3/8 w - - 0 1",
+];
+
+/// Bookkeeping shared by every node of a single search
+#[derive(Default)]
+pub struct SearchCtx {
+    pub nodes: u64,
+    /// Nodes visited by the quiescence search
+    pub qnodes: u64,
+    /// Quiescence search looks at all check evasions instead of standing pat in check
+    pub qsearch_evasions: bool,
+    /// Distance from the root of the current node
+    pub ply: u16,
+    /// Zobrist keys of the game and search path positions before the current one
+    pub history: Vec<u64>,
+    /// Searched first at the root, it's the best move of the previous iteration
+    pub root_move: Option<BitMove>,
+    /// Number of best root moves reported with their lines
+    pub multipv: usize,
+    /// Root moves skipped while searching for the next multi-PV line
+    pub root_excluded: Vec<BitMove>,
+    pub pv: PvTable,
+    pub deadline: Option<Instant>,
+    pub node_limit: Option<u64>,
+    /// Set when a limit was hit, the running iteration result must be dropped
+    pub stopped: bool,
+    /// Lives across searches of the same game, shared by all searcher threads
+    pub tt: Arc<TranspositionTable>,
+    /// Stops the search from another thread, like a GUI `stop` command.
+    /// It ends the main search, which stops the helper threads,
+    /// and stays raised until its owner lowers it.
+    pub stop: Arc<AtomicBool>,
+    /// Zero for the main searcher, helper threads of Lazy SMP get ids from one
+    pub helper_id: usize,
+    /// Searcher threads including the main one
+    pub threads: usize,
+    /// Killer and history heuristics, without it moves are searched in generation order
+    pub move_ordering: bool,
+    pub ordering: MoveOrdering,
+    /// Selective search, switchable to check that each one gains with the network eval
+    pub null_move: bool,
+    pub lmr: bool,
+    /// Futility pruning and razoring near the leaves
+    pub futility: bool,
+    /// Principal variation search, moves after the first one get a zero window
+    pub pvs: bool,
+    /// Root is searched with a window around the score of the previous iteration
+    pub aspiration: bool,
+    /// Set while the null move child is searched, two null moves in a row aren't allowed
+    after_null: bool,
+    pub extensions: Extensions,
+    /// Plies added by extensions on the path to the current node
+    path_ext: u16,
+    /// Children of depth 1 nodes are evaluated as a single network batch
+    pub batch_leaves: bool,
+    /// Network scores memoized across searches and games
+    pub eval_cache: EvalCache,
+    /// Monte Carlo tree search is used instead of alpha-beta when set
+    pub mcts: Option<MctsOptions>,
+    /// Endgame tablebases, shared by all searcher threads
+    pub syzygy: Option<Arc<Syzygy>>,
+    /// Network, handcrafted or blended leaf scores
+    pub eval: EvalBlend,
 }
 
-pub fn test_ocl(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
-    let state_white = args.get_one::<String>("ModelStateWhite").unwrap();
-    let state_black = args.get_one::<String>("ModelStateBlack").unwrap();
+impl SearchCtx {
+    pub fn with_hash(size_mb: usize) -> Self {
+        Self {
+            tt: Arc::new(TranspositionTable::new(size_mb)),
+            move_ordering: true,
+            batch_leaves: true,
+            null_move: true,
+            lmr: true,
+            futility: true,
+            pvs: true,
+            aspiration: true,
+            extensions: Extensions::all(),
+            eval_cache: EvalCache::new(DEFAULT_EVAL_CACHE_MB),
+            threads: 1,
+            multipv: 1,
+            ..Default::default()
+        }
+    }
 
-    let mut mdl_white = SequentialOcl::new()?;
-    let mut mdl_black = SequentialOcl::new()?;
+    /// Reads search options shared by `play` and `test`
+    pub fn from_args(args: &ArgMatches) -> Self {
+        let mut ctx = Self::with_hash(*args.get_one::<usize>("Hash").unwrap());
+        ctx.qsearch_evasions = args.contains_id("QsEvasions");
+        ctx.threads = *args.get_one::<usize>("Threads").unwrap();
+        ctx.multipv = *args.get_one::<usize>("MultiPv").unwrap();
+        ctx.null_move = !args.contains_id("NoNullMove");
+        ctx.lmr = !args.contains_id("NoLmr");
+        ctx.futility = !args.contains_id("NoFutility");
+        ctx.pvs = !args.contains_id("NoPvs");
+        ctx.aspiration = !args.contains_id("NoAspiration");
+        ctx.extensions = Extensions::parse(
+            args.get_one::<String>("Extensions").unwrap(),
+            *args.get_one::<u16>("ExtensionBudget").unwrap(),
+        )
+        .unwrap_or_else(|e| {
+            warn!("{}, extensions are disabled", e);
+            Extensions::default()
+        });
 
-    fill_ocl_model_with_layers(&mut mdl_white, false);
-    fill_ocl_model_with_layers(&mut mdl_black, false);
+        if args.get_one::<String>("Search").unwrap() == "mcts" {
+            ctx.mcts = Some(MctsOptions {
+                playouts: *args.get_one::<u32>("Playouts").unwrap(),
+                cpuct: *args.get_one::<f32>("Cpuct").unwrap(),
+            });
+        }
 
-    mdl_white.load_state(state_white)?;
-    mdl_black.load_state(state_black)?;
+        ctx.eval = EvalBlend::parse(
+            args.get_one::<String>("Eval").unwrap(),
+            *args.get_one::<f32>("NetWeight").unwrap(),
+            args.get_one::<f32>("NetWeightEndgame").cloned(),
+        )
+        .unwrap_or_else(|e| {
+            warn!("{}, network evaluation is used", e);
+            EvalBlend::default()
+        });
 
-    continue_test(args, mdl_white, mdl_black)
-}
+        if let Some(path) = args.get_one::<String>("SyzygyPath") {
+            ctx.syzygy = Syzygy::open(path)
+                .map(Arc::new)
+                .map_err(|e| warn!("Tablebases are disabled : {}", e))
+                .ok();
+        }
 
-pub fn continue_test<T: Model + Serialize + Clone>(
-    args: &ArgMatches,
-    mdl_white: T,
-    mdl_black: T,
-) -> Result<(), Box<dyn Error>> {
-    let fen_str = args.get_one::<String>("Fen").unwrap();
+        let cache_mb = *args.get_one::<usize>("EvalCache").unwrap();
 
-    let mut board = Board::from_fen(fen_str.as_str()).unwrap();
-    let mut vec_possible = Vec::new();
+        ctx.eval_cache = if args.contains_id("PersistEvalCache") {
+            EvalCache::persistent(cache_mb, &model_states(args)).unwrap_or_else(|e| {
+                warn!("Eval cache won't be saved : {}", e);
+                EvalCache::new(cache_mb)
+            })
+        } else {
+            EvalCache::new(cache_mb)
+        };
 
-    vec_possible.push(encode_board(&mut board, 0.0).unwrap());
+        ctx
+    }
+
+    /// Context of a Lazy SMP helper thread with the same search options,
+    /// its own eval cache is as big as the main one and isn't saved
+    pub fn helper(&self, helper_id: usize) -> Self {
+        Self {
+            tt: self.tt.clone(),
+            qsearch_evasions: self.qsearch_evasions,
+            move_ordering: self.move_ordering,
+            batch_leaves: self.batch_leaves,
+            null_move: self.null_move,
+            lmr: self.lmr,
+            futility: self.futility,
+            pvs: self.pvs,
+            aspiration: self.aspiration,
+            extensions: self.extensions,
+            eval_cache: EvalCache::new(self.eval_cache.size_mb()),
+            syzygy: self.syzygy.clone(),
+            eval: self.eval,
+            helper_id,
+            ..Default::default()
+        }
+    }
 
-    let mut net = Orchestra::new_for_eval(mdl_white).test_batch_size(1);
-    let mut net_black = Orchestra::new_for_eval(mdl_black).test_batch_size(1);
+    /// Reallocates the transposition table, helper threads get it with the next search
+    pub fn resize_hash(&mut self, size_mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(size_mb));
+    }
+
+    /// Must be called on a new game, so positions of the previous one are forgotten
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.ordering.clear();
+    }
+
+    /// Resets the counters and limits before a new search
+    pub fn new_search(&mut self) {
+        // helpers search along with the main thread, which ages the shared table
+        if self.helper_id == 0 {
+            self.tt.new_search();
+        }
+        self.ordering.new_search();
+        self.nodes = 0;
+        self.qnodes = 0;
+        self.ply = 0;
+        self.root_move = None;
+        self.root_excluded.clear();
+        self.after_null = false;
+        self.path_ext = 0;
+        self.deadline = None;
+        self.node_limit = None;
+        self.stopped = false;
+    }
 
-    if board.turn() == pleco::Player::White {
-        let out = net
-            .eval_one(encode_board(&mut board, 0.0).unwrap().input)
-            .unwrap();
-        let out_b = out.borrow();
+    pub fn total_nodes(&self) -> u64 {
+        self.nodes + self.qnodes
+    }
+
+    fn check_limits(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+
+        if let Some(limit) = self.node_limit {
+            self.stopped |= self.total_nodes() >= limit;
+        }
+
+        if self.total_nodes() % LIMITS_CHECK_INTERVAL != 0 {
+            return self.stopped;
+        }
+
+        if let Some(deadline) = self.deadline {
+            self.stopped |= Instant::now() >= deadline;
+        }
+
+        self.stopped |= self.stop.load(Ordering::Relaxed);
+
+        self.stopped
+    }
+}
+
+/// Summary of a completed iterative deepening iteration
+pub struct IterationInfo {
+    pub depth: u16,
+    pub best_move: BitMove,
+    pub score: i16,
+    /// Principal variations of the best root moves, the best one first
+    pub lines: Vec<PvLine>,
+    /// Main and quiescence search nodes
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub elapsed: Duration,
+}
 
-        info!("Current board eval : {}", out_b.first().unwrap());
+/// Moves to mate for mate scores, negative if the side to move gets mated
+pub fn mate_in(score: i16) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE_V - score + 1) as i32 / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-((MATE_V + score) as i32 / 2))
     } else {
-        let out = net_black
-            .eval_one(encode_board(&mut board, 0.0).unwrap().input)
-            .unwrap();
-        let out_b = out.borrow();
+        None
+    }
+}
 
-        info!("Current board eval : {}", out_b.first().unwrap());
+/// Mate and tablebase scores are stored relative to the node, so they stay valid at another ply
+fn score_to_tt(score: i16, ply: u16) -> i16 {
+    if score >= TB_BOUND {
+        score + ply as i16
+    } else if score <= -TB_BOUND {
+        score - ply as i16
+    } else {
+        score
     }
+}
 
-    let depth = 4;
-    let is_inv = if board.turn() == pleco::Player::White {
-        depth % 2 == 1
+fn score_from_tt(score: i16, ply: u16) -> i16 {
+    if score >= TB_BOUND {
+        score - ply as i16
+    } else if score <= -TB_BOUND {
+        score + ply as i16
     } else {
-        depth % 2 == 0
+        score
+    }
+}
+
+/// Zobrist keys of positions since the last irreversible move, the oldest first.
+/// Board must keep its move history, see `Board::parallel_clone`.
+pub fn game_history(board: &Board) -> Vec<u64> {
+    let mut b = board.parallel_clone();
+    let cnt = board.rule_50().max(0) as usize;
+    let mut keys = Vec::with_capacity(cnt);
+
+    // positions set up from fen have no previous move
+    while keys.len() < cnt && b.last_move().is_some() {
+        b.undo_move();
+        keys.push(b.zobrist());
+    }
+
+    keys.reverse();
+    keys
+}
+
+/// Neither side could mate with bare kings and a single minor piece
+fn insufficient_material(board: &Board) -> bool {
+    let mut minors = 0;
+
+    for p in [Player::White, Player::Black] {
+        if board.count_piece(p, PieceType::P) > 0
+            || board.count_piece(p, PieceType::R) > 0
+            || board.count_piece(p, PieceType::Q) > 0
+        {
+            return false;
+        }
+
+        minors += board.count_piece(p, PieceType::N) + board.count_piece(p, PieceType::B);
+    }
+
+    minors <= 1
+}
+
+/// Fifty-move rule, insufficient material or a position repeated since the last irreversible move.
+/// A single repetition is enough, the side which repeated could do it again.
+pub fn is_draw(board: &Board, ctx: &SearchCtx) -> bool {
+    let rule_50 = board.rule_50().max(0) as usize;
+
+    if rule_50 >= 100 || insufficient_material(board) {
+        return true;
+    }
+
+    let key = board.zobrist();
+
+    // same side to move positions only, the last key is the parent
+    ctx.history
+        .iter()
+        .rev()
+        .take(rule_50)
+        .skip(1)
+        .step_by(2)
+        .any(|k| *k == key)
+}
+
+/// Reasons a game is over in the played position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameEnd {
+    /// Side to move is mated
+    Checkmate,
+    Stalemate,
+    /// Position occurred for the third time
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+}
+
+impl GameEnd {
+    pub fn is_draw(self) -> bool {
+        self != GameEnd::Checkmate
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            GameEnd::Checkmate => "Checkmate",
+            GameEnd::Stalemate => "Stalemate",
+            GameEnd::Repetition => "Draw by threefold repetition",
+            GameEnd::FiftyMoves => "Draw by fifty-move rule",
+            GameEnd::InsufficientMaterial => "Draw by insufficient material",
+        }
+    }
+}
+
+/// Whether the game is over by the rules, unlike `is_draw` a repetition must happen three times.
+/// Board must keep its move history, see `Board::parallel_clone`.
+pub fn game_end(board: &Board) -> Option<GameEnd> {
+    if board.checkmate() {
+        return Some(GameEnd::Checkmate);
+    }
+
+    if board.stalemate() {
+        return Some(GameEnd::Stalemate);
+    }
+
+    if board.rule_50() >= 100 {
+        return Some(GameEnd::FiftyMoves);
+    }
+
+    if insufficient_material(board) {
+        return Some(GameEnd::InsufficientMaterial);
+    }
+
+    let key = board.zobrist();
+
+    // same side to move positions only, the last key is the parent
+    let repeated = game_history(board)
+        .iter()
+        .rev()
+        .skip(1)
+        .step_by(2)
+        .filter(|k| **k == key)
+        .count();
+
+    if repeated >= 2 {
+        return Some(GameEnd::Repetition);
+    }
+
+    None
+}
+
+/// Search stopped before completing its first iteration has no best move, any legal one is played then
+pub fn playable_move(board: &Board, best_move: BitMove) -> Option<BitMove> {
+    if !best_move.is_null() {
+        return Some(best_move);
+    }
+
+    warn!("Search found no best move, playing the first legal one");
+    board.generate_moves().iter().next().copied()
+}
+
+pub fn test(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_models(args)?;
+
+    continue_test(args, models)
+}
+
+pub fn test_ocl(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_ocl_models(args)?;
+
+    continue_test(args, models)
+}
+
+pub fn continue_test<T: Model + Serialize + Clone + Send + 'static>(
+    args: &ArgMatches,
+    models: Models<T>,
+) -> Result<(), Box<dyn Error>> {
+    let mut board = match args.get_one::<String>("Fen") {
+        Some(fen_str) => Board::from_fen(fen_str.as_str()).unwrap(),
+        None => Board::start_pos(), // bench
     };
+    let mut vec_possible = Vec::new();
+
+    vec_possible.push(encode_board(&mut board, 0.0).unwrap());
+
+    let mut ctx = SearchCtx::from_args(args);
+    let mut pool = SmpPool::new(&models, &ctx);
+
+    let mut eval = Evaluator::new(models);
 
-    let best_move = my_alpha_beta_search(
-        &mut board,
-        -15000,
-        15000,
-        depth,
-        &mut net,
-        &mut net_black,
-        is_inv,
+    info!(
+        "Current board eval : {} cp",
+        eval.evaluate(&mut board, &mut ctx)
     );
-    // let best_move = my_minimax(&mut board, 2, &mut net);
+
+    let mut limits = SearchLimits::from_args(args, 4);
+
+    if args.contains_id("Infinite") {
+        limits = SearchLimits {
+            infinite: true,
+            ..Default::default()
+        };
+        spawn_stop_on_enter(ctx.stop.clone());
+        info!("Analyzing, press Enter to stop");
+    }
+
+    if args.contains_id("Bench") {
+        bench(args, &limits, &mut eval);
+        return Ok(());
+    }
+
+    if args.contains_id("BenchOrdering") {
+        // same fixed depth search from scratch, without and with move ordering
+        let bench_limits = SearchLimits::with_depth(limits.max_depth());
+
+        for move_ordering in [false, true] {
+            let mut ctx = SearchCtx::from_args(args);
+            ctx.move_ordering = move_ordering;
+
+            let res = iterative_deepening(&mut board, &bench_limits, &mut eval, &mut ctx, |_| true);
+
+            info!(
+                "Move ordering {} : {} - {}, depth {}, nodes {} (quiescence {})",
+                if move_ordering { "on" } else { "off" },
+                res.bit_move,
+                res.score,
+                bench_limits.max_depth(),
+                ctx.total_nodes(),
+                ctx.qnodes
+            );
+        }
+
+        return Ok(());
+    }
+
+    if args.contains_id("BenchBatch") {
+        // same fixed depth search from scratch, leaves evaluated one by one and in batches
+        let bench_limits = SearchLimits::with_depth(limits.max_depth());
+
+        for batch_leaves in [false, true] {
+            let mut ctx = SearchCtx::from_args(args);
+            ctx.batch_leaves = batch_leaves;
+
+            let start = Instant::now();
+            let res = iterative_deepening(&mut board, &bench_limits, &mut eval, &mut ctx, |_| true);
+            let elapsed = start.elapsed();
+
+            info!(
+                "Leaf batching {} : {} - {}, depth {}, nodes {}, batches {} ({} positions), {} ms, {} nodes/s",
+                if batch_leaves { "on" } else { "off" },
+                res.bit_move,
+                res.score,
+                bench_limits.max_depth(),
+                ctx.total_nodes(),
+                eval.leaf.batches,
+                eval.leaf.positions,
+                elapsed.as_millis(),
+                nodes_per_sec(ctx.total_nodes(), elapsed)
+            );
+        }
+
+        return Ok(());
+    }
+
+    if let Some(book) = Book::from_args(args)? {
+        for (m, weight) in book.moves(&board) {
+            info!("Book move : {}, weight {}", m, weight);
+        }
+
+        if let Some(m) = book.pick(&board) {
+            info!("Best move : {} (book)", m);
+            return Ok(());
+        }
+    }
+
+    let best_move = pool.search(&mut board, &limits, &mut eval, &mut ctx, |it| {
+        info!(
+            "Depth {} : {} - {}, nodes {} (quiescence {}), {} ms",
+            it.depth,
+            it.best_move,
+            it.score,
+            it.nodes,
+            it.qnodes,
+            it.elapsed.as_millis()
+        );
+
+        for (i, line) in it.lines.iter().enumerate() {
+            info!("  {}. {} : {}", i + 1, line.score, line.moves_str());
+        }
+
+        true
+    });
+    // let best_move = my_minimax(&mut board, 2, &mut eval, &mut ctx);
 
     info!("Best move : {} - {}", best_move.bit_move, best_move.score);
+    info!(
+        "Nodes searched : {}, helper threads : {}",
+        ctx.total_nodes(),
+        pool.helper_nodes
+    );
+
+    ctx.eval_cache.log_stats();
 
     Ok(())
 }
 
-pub fn my_minimax<T: Model + Serialize + Clone>(
+/// Raises `stop` once a line is read from stdin
+fn spawn_stop_on_enter(stop: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = std::io::stdin().read_line(&mut line);
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+fn nodes_per_sec(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-3)) as u64
+}
+
+/// Fixed depth search over `BENCH_FENS` with fresh tables for each position
+fn bench<T: Model + Serialize + Clone>(
+    args: &ArgMatches,
+    limits: &SearchLimits,
+    eval: &mut Evaluator<T>,
+) {
+    let bench_limits = SearchLimits::with_depth(limits.max_depth());
+    let start = Instant::now();
+    let mut total_nodes = 0;
+
+    for fen in BENCH_FENS.iter() {
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut ctx = SearchCtx::from_args(args);
+
+        let res = iterative_deepening(&mut board, &bench_limits, eval, &mut ctx, |_| true);
+
+        info!(
+            "{} : {} - {}, nodes {}",
+            fen,
+            res.bit_move,
+            res.score,
+            ctx.total_nodes()
+        );
+        total_nodes += ctx.total_nodes();
+    }
+
+    let elapsed = start.elapsed();
+
+    info!(
+        "Bench depth {} : {} nodes, {} ms, {} nodes/s",
+        bench_limits.max_depth(),
+        total_nodes,
+        elapsed.as_millis(),
+        nodes_per_sec(total_nodes, elapsed)
+    );
+}
+
+/// Root search with a narrow window around `guess`, which is widened on fail low or high
+fn aspiration_search<T: Model + Serialize + Clone>(
     board: &mut Board,
+    guess: i16,
     depth: u16,
-    net: &mut Orchestra<T>,
-    net_black: &mut Orchestra<T>,
-    inv_val: bool,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
 ) -> ScoringMove {
-    if depth == 0 {
-        let enc_b = encode_board(board, 0.0).unwrap();
-        let mut score_move;
-
-        if board.turn() == pleco::Player::White {
-            let out_net = net.eval_one(enc_b.input).unwrap();
-            let out_net_b = out_net.borrow();
-            let score = (out_net_b.first().unwrap() - 0.5) * 15000.0;
-            score_move = ScoringMove::new_score(BitMove::new(0), score as i16);
+    let mut delta = ASPIRATION_WINDOW as i32;
+    let mut alpha = (guess as i32 - delta).max(-INF_V as i32) as i16;
+    let mut beta = (guess as i32 + delta).min(INF_V as i32) as i16;
+
+    loop {
+        let res = my_alpha_beta_search(board, alpha, beta, depth, eval, ctx);
+
+        if ctx.stopped {
+            return res;
+        }
+
+        delta *= 2;
+
+        if res.score <= alpha && alpha > -INF_V {
+            alpha = (alpha as i32 - delta).max(-INF_V as i32) as i16;
+        } else if res.score >= beta && beta < INF_V {
+            beta = (beta as i32 + delta).min(INF_V as i32) as i16;
+        } else {
+            return res;
+        }
+    }
+}
+
+/// DTZ-optimal root move reported as a single iteration, if the position is in the tablebases
+pub fn tablebase_root(board: &Board, ctx: &SearchCtx, start: Instant) -> Option<IterationInfo> {
+    let (mv, score) = ctx.syzygy.as_ref()?.probe_root(board)?;
+
+    Some(IterationInfo {
+        depth: 1,
+        best_move: mv,
+        score,
+        lines: vec![PvLine {
+            score,
+            moves: vec![mv],
+        }],
+        nodes: 0,
+        qnodes: 0,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Runs `my_alpha_beta_search` with increasing depth until `limits` are reached.
+/// The search is aborted in the middle of an iteration when the hard time limit,
+/// the node limit or `ctx.stop` is hit, then the best move found so far is returned.
+/// `on_iteration` is called after each completed iteration, it could return false to stop deepening.
+pub fn iterative_deepening<T, F>(
+    board: &mut Board,
+    limits: &SearchLimits,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
+    mut on_iteration: F,
+) -> ScoringMove
+where
+    T: Model + Serialize + Clone,
+    F: FnMut(&IterationInfo) -> bool,
+{
+    let start = Instant::now();
+    let budget = limits.budget();
+
+    ctx.new_search();
+    ctx.history = game_history(board);
+    eval.clear();
+    ctx.node_limit = limits.nodes;
+    ctx.deadline = budget.map(|b| start + b.hard);
+
+    let legal_moves = board.generate_moves();
+
+    if legal_moves.is_empty() {
+        if board.in_check() {
+            return ScoringMove::blank(-MATE_V);
         } else {
-            let out_net = net_black.eval_one(enc_b.input).unwrap();
-            let out_net_b = out_net.borrow();
-            let score = (out_net_b.first().unwrap() - 0.5) * 15000.0;
-            score_move = ScoringMove::new_score(BitMove::new(0), score as i16);
+            return ScoringMove::blank(DRAW_V);
+        }
+    }
+
+    // tablebase move keeps the win, which the network could miss in simple endgames
+    if let Some(info) = tablebase_root(board, ctx, start) {
+        on_iteration(&info);
+        return ScoringMove::new_score(info.best_move, info.score);
+    }
+
+    // fallback if the first iteration is aborted
+    let mut best_move = ScoringMove::new_score(legal_moves[0], 0);
+    let multipv = ctx.multipv.clamp(1, legal_moves.len());
+    let mut prev_score = 0;
+
+    // odd helpers run one iteration ahead, so threads don't search the same depth in lockstep
+    let first_depth = (1 + (ctx.helper_id % 2) as u16).min(limits.max_depth());
+
+    for depth in first_depth..=limits.max_depth() {
+        let mut lines: Vec<PvLine> = Vec::with_capacity(multipv);
+
+        // each next line is searched without root moves of the previous ones
+        ctx.root_excluded.clear();
+        for pv_idx in 0..multipv {
+            let res = if pv_idx == 0
+                && ctx.aspiration
+                && depth >= ASPIRATION_MIN_DEPTH
+                && prev_score.abs() < MATE_BOUND
+            {
+                aspiration_search(board, prev_score, depth, eval, ctx)
+            } else {
+                my_alpha_beta_search(board, -INF_V, INF_V, depth, eval, ctx)
+            };
+
+            if ctx.stopped || res.bit_move.is_null() {
+                // root move completed by the aborted iteration beats the previous best one
+                if ctx.stopped && pv_idx == 0 && !res.bit_move.is_null() {
+                    best_move = res;
+                }
+                break;
+            }
+
+            let mut moves = ctx.pv.line(0).to_vec();
+            if moves.first() != Some(&res.bit_move) {
+                moves = vec![res.bit_move];
+            }
+
+            lines.push(PvLine {
+                score: res.score,
+                moves,
+            });
+            ctx.root_excluded.push(res.bit_move);
         }
+        ctx.root_excluded.clear();
 
-        if inv_val {
-            score_move.score = -1 * score_move.score;
+        if ctx.stopped || lines.is_empty() {
+            break;
         }
 
-        return score_move;
+        let res = ScoringMove::new_score(lines[0].moves[0], lines[0].score);
+        best_move = res;
+        prev_score = res.score;
+        ctx.root_move = Some(res.bit_move);
+
+        let info = IterationInfo {
+            depth,
+            best_move: best_move.bit_move,
+            score: res.score,
+            lines,
+            nodes: ctx.total_nodes(),
+            qnodes: ctx.qnodes,
+            elapsed: start.elapsed(),
+        };
+
+        if !on_iteration(&info) {
+            break;
+        }
+
+        if let Some(b) = budget {
+            if info.elapsed >= b.soft {
+                break;
+            }
+        }
+
+        if let Some(n) = limits.nodes {
+            if ctx.total_nodes() >= n {
+                break;
+            }
+        }
+
+        // deeper iterations can't find a shorter mate
+        if res.score.abs() >= MATE_BOUND && depth as i16 >= MATE_V - res.score.abs() {
+            break;
+        }
+    }
+
+    best_move
+}
+
+pub fn my_minimax<T: Model + Serialize + Clone>(
+    board: &mut Board,
+    depth: u16,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
+) -> ScoringMove {
+    if depth == 0 {
+        let score = eval.evaluate(board, ctx);
+        return ScoringMove::new_score(BitMove::new(0), score);
+    }
+
+    if depth == 1 {
+        let moves = board.generate_scoring_moves();
+        eval.prefetch(board, &moves, ctx);
     }
 
     let mapped_vals = board
@@ -138,7 +857,7 @@ pub fn my_minimax<T: Model + Serialize + Clone>(
         .into_iter()
         .map(|mut m: ScoringMove| {
             board.apply_move(m.bit_move);
-            m.score = -my_minimax(board, depth - 1, net, net_black, inv_val).score;
+            m.score = -my_minimax(board, depth - 1, eval, ctx).score;
             board.undo_move();
             m
         });
@@ -152,60 +871,409 @@ pub fn my_minimax<T: Model + Serialize + Clone>(
 pub fn my_alpha_beta_search<T: Model + Serialize + Clone>(
     board: &mut Board,
     mut alpha: i16,
-    beta: i16,
+    mut beta: i16,
     depth: u16,
-    net: &mut Orchestra<T>,
-    net_black: &mut Orchestra<T>,
-    inv_val: bool,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
 ) -> ScoringMove {
+    ctx.nodes += 1;
+    ctx.pv.clear(ctx.ply);
+
+    let after_null = std::mem::take(&mut ctx.after_null);
+
+    if ctx.check_limits() {
+        return ScoringMove::blank(DRAW_V);
+    }
+
+    if ctx.ply > 0 {
+        if is_draw(board, ctx) {
+            return ScoringMove::blank(DRAW_V);
+        }
+
+        // mate distance pruning, a shorter mate was already found
+        alpha = alpha.max(-MATE_V + ctx.ply as i16);
+        beta = beta.min(MATE_V - ctx.ply as i16 - 1);
+
+        if alpha >= beta {
+            return ScoringMove::blank(alpha);
+        }
+
+        if let Some(score) = ctx.syzygy.as_ref().and_then(|tb| tb.probe_wdl(board, ctx.ply)) {
+            return ScoringMove::blank(score);
+        }
+    }
+
+    let key = board.zobrist();
+    let mut tt_move = None;
+
+    if let Some(entry) = ctx.tt.probe(key) {
+        let score = score_from_tt(entry.score, ctx.ply);
+
+        if ctx.ply > 0 && entry.depth >= depth {
+            let hit = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+
+            if hit {
+                return ScoringMove::new_score(entry.best_move, score);
+            }
+        }
+
+        if !entry.best_move.is_null() {
+            tt_move = Some(entry.best_move);
+        }
+    }
+
     if depth == 0 {
-        let enc_b = encode_board(board, 0.0).unwrap();
-        let mut score_move;
-
-        if board.turn() == pleco::Player::White {
-            let out_net = net.eval_one(enc_b.input).unwrap();
-            let out_net_b = out_net.borrow();
-            let score = (out_net_b.first().unwrap() - 0.5) * 15000.0;
-            score_move = ScoringMove::new_score(BitMove::new(0), score as i16);
-        } else {
-            let out_net = net_black.eval_one(enc_b.input).unwrap();
-            let out_net_b = out_net.borrow();
-            let score = (out_net_b.first().unwrap() - 0.5) * 15000.0;
-            score_move = ScoringMove::new_score(BitMove::new(0), score as i16);
+        let score = quiescence(board, alpha, beta, 0, eval, ctx);
+
+        if ctx.stopped {
+            return ScoringMove::blank(DRAW_V);
         }
 
-        if inv_val {
-            score_move.score = -1 * score_move.score;
+        let bound = if score <= alpha {
+            Bound::Upper
+        } else if score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+
+        ctx.tt
+            .store(key, 0, bound, score_to_tt(score, ctx.ply), BitMove::null());
+
+        return ScoringMove::blank(score);
+    }
+
+    let in_check = board.in_check();
+
+    // computed only if some pruning below could use it
+    let static_eval = if ctx.ply > 0
+        && !in_check
+        && ((ctx.null_move && depth >= NULL_MOVE_MIN_DEPTH)
+            || (ctx.futility && depth <= FUTILITY_MAX_DEPTH))
+    {
+        Some(eval.evaluate(board, ctx))
+    } else {
+        None
+    };
+
+    // razoring, hopeless position near the leaves is only checked for captures
+    if let Some(static_eval) = static_eval {
+        if ctx.futility
+            && depth <= RAZOR_MAX_DEPTH
+            && static_eval + RAZOR_MARGIN * depth as i16 <= alpha
+        {
+            let score = quiescence(board, alpha, beta, 0, eval, ctx);
+
+            if ctx.stopped {
+                return ScoringMove::blank(DRAW_V);
+            }
+
+            if score <= alpha {
+                return ScoringMove::blank(score);
+            }
         }
+    }
 
-        return score_move;
+    // null move pruning, position is good enough even if the opponent moves twice
+    if let Some(static_eval) = static_eval {
+        if ctx.null_move
+            && !after_null
+            && depth >= NULL_MOVE_MIN_DEPTH
+            && static_eval >= beta
+            && beta.abs() < MATE_BOUND
+            && has_non_pawn_material(board)
+        {
+            let r = (2 + depth / 4).min(depth - 1);
+
+            ctx.history.push(key);
+            unsafe {
+                board.apply_null_move();
+            }
+            ctx.ply += 1;
+            ctx.after_null = true;
+            let score =
+                -my_alpha_beta_search(board, -beta, -beta + 1, depth - 1 - r, eval, ctx).score;
+            ctx.after_null = false;
+            ctx.ply -= 1;
+            unsafe {
+                board.undo_null_move();
+            }
+            ctx.history.pop();
+
+            if ctx.stopped {
+                return ScoringMove::blank(DRAW_V);
+            }
+
+            if score >= beta {
+                // unproven mates aren't returned
+                return ScoringMove::blank(if score >= MATE_BOUND { beta } else { score });
+            }
+        }
     }
 
     let mut moves = board.generate_scoring_moves();
 
     if moves.is_empty() {
         if board.in_check() {
-            return ScoringMove::blank(-MATE_V);
+            return ScoringMove::blank(-MATE_V + ctx.ply as i16);
         } else {
             return ScoringMove::blank(DRAW_V);
         }
     }
 
+    let first_move = if ctx.ply == 0 {
+        ctx.root_move.or(tt_move)
+    } else {
+        tt_move
+    };
+
+    if ctx.move_ordering {
+        ctx.ordering
+            .order_moves(board, &mut moves, first_move, ctx.ply);
+    } else if let Some(first_move) = first_move {
+        if let Some(pos) = moves.iter().position(|m| m.bit_move == first_move) {
+            moves.swap(0, pos);
+        }
+    }
+
+    // children are leaves, their stand pat scores are evaluated at once
+    if depth == 1 && ctx.batch_leaves && moves.len() > 1 {
+        eval.prefetch(board, &moves, ctx);
+    }
+
+    // root entry would be wrong while some root moves are skipped
+    let store_tt = ctx.ply > 0 || ctx.root_excluded.is_empty();
+
+    // quiet moves can't raise a position this bad over alpha
+    let futile = match static_eval {
+        Some(static_eval) => {
+            ctx.futility
+                && depth <= FUTILITY_MAX_DEPTH
+                && alpha.abs() < MATE_BOUND
+                && static_eval + FUTILITY_MARGIN * depth as i16 <= alpha
+        }
+        None => false,
+    };
+
+    let single_reply = ctx.ply > 0 && moves.len() == 1;
+
+    let alpha_orig = alpha;
     let mut best_move = ScoringMove::blank(alpha);
+    let mut searched = 0;
     for mov in moves.iter_mut() {
+        if ctx.ply == 0 && ctx.root_excluded.contains(&mov.bit_move) {
+            continue;
+        }
+
+        let quiet = !mov.bit_move.is_capture() && !mov.bit_move.is_promo();
+        let tactical = !quiet || in_check || board.gives_check(mov.bit_move);
+
+        if futile && searched > 0 && !tactical {
+            continue;
+        }
+
+        let extension = if ctx.path_ext < ctx.extensions.budget
+            && ctx.extensions.extends(board, mov.bit_move, single_reply)
+        {
+            1
+        } else {
+            0
+        };
+        let child_depth = depth - 1 + extension;
+
+        // late quiet moves are searched shallower with a null window first
+        let reduction = if ctx.lmr
+            && ctx.ply > 0
+            && depth >= LMR_MIN_DEPTH
+            && searched >= LMR_FULL_MOVES
+            && !tactical
+            && extension == 0
+        {
+            if depth >= 6 && searched >= 2 * LMR_FULL_MOVES {
+                2
+            } else {
+                1
+            }
+        } else {
+            0
+        };
+
+        ctx.history.push(key);
         board.apply_move(mov.bit_move);
-        mov.score =
-            -my_alpha_beta_search(board, -beta, -alpha, depth - 1, net, net_black, inv_val).score;
+        ctx.ply += 1;
+        ctx.path_ext += extension;
+
+        // Reduced zero window search, then full depth zero window search (PVS)
+        // and full window search last, each one only if the previous beat alpha
+        let mut research = true;
+        if reduction > 0 {
+            mov.score = -my_alpha_beta_search(
+                board,
+                -alpha - 1,
+                -alpha,
+                child_depth - reduction,
+                eval,
+                ctx,
+            )
+            .score;
+
+            research = mov.score > alpha;
+        }
+
+        if research && ctx.pvs && searched > 0 && !ctx.stopped {
+            mov.score =
+                -my_alpha_beta_search(board, -alpha - 1, -alpha, child_depth, eval, ctx).score;
+
+            research = mov.score > alpha && mov.score < beta;
+        }
+
+        if research && !ctx.stopped {
+            mov.score = -my_alpha_beta_search(board, -beta, -alpha, child_depth, eval, ctx).score;
+        }
+
+        searched += 1;
+        ctx.path_ext -= extension;
+        ctx.ply -= 1;
         board.undo_move();
+        ctx.history.pop();
+
+        if ctx.stopped {
+            return best_move;
+        }
 
         if mov.score > alpha {
             alpha = mov.score;
             if alpha >= beta {
+                if store_tt {
+                    ctx.tt.store(
+                        key,
+                        depth,
+                        Bound::Lower,
+                        score_to_tt(mov.score, ctx.ply),
+                        mov.bit_move,
+                    );
+                }
+                ctx.ordering.update_cutoff(mov.bit_move, ctx.ply, depth);
                 return *mov;
             }
             best_move = *mov;
+            ctx.pv.update(ctx.ply, mov.bit_move);
         }
     }
 
+    if !store_tt {
+        return best_move;
+    }
+
+    if best_move.score > alpha_orig {
+        ctx.tt.store(
+            key,
+            depth,
+            Bound::Exact,
+            score_to_tt(best_move.score, ctx.ply),
+            best_move.bit_move,
+        );
+    } else {
+        ctx.tt.store(
+            key,
+            depth,
+            Bound::Upper,
+            score_to_tt(alpha_orig, ctx.ply),
+            BitMove::null(),
+        );
+    }
+
     best_move
 }
+
+/// Null move is unsafe in zugzwang, which mostly happens with pawns and king only
+fn has_non_pawn_material(board: &Board) -> bool {
+    let p = board.turn();
+
+    [PieceType::N, PieceType::B, PieceType::R, PieceType::Q]
+        .iter()
+        .any(|pt| board.count_piece(p, *pt) > 0)
+}
+
+/// Searches captures and queen promotions until the position is quiet,
+/// so the network doesn't evaluate positions with a pending recapture.
+/// The side to move could stand pat with the network score instead of capturing.
+pub fn quiescence<T: Model + Serialize + Clone>(
+    board: &mut Board,
+    mut alpha: i16,
+    beta: i16,
+    qply: u16,
+    eval: &mut Evaluator<T>,
+    ctx: &mut SearchCtx,
+) -> i16 {
+    ctx.qnodes += 1;
+
+    if ctx.check_limits() {
+        return DRAW_V;
+    }
+
+    if qply > 0 && is_draw(board, ctx) {
+        return DRAW_V;
+    }
+
+    let evasions = ctx.qsearch_evasions && qply < MAX_QSEARCH_PLY && board.in_check();
+
+    if !evasions {
+        let stand_pat = eval.evaluate(board, ctx);
+
+        if stand_pat >= beta || qply >= MAX_QSEARCH_PLY {
+            return stand_pat;
+        }
+
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+    }
+
+    let all_moves = board.generate_moves();
+
+    if evasions && all_moves.is_empty() {
+        return -MATE_V + ctx.ply as i16;
+    }
+
+    // captures losing material are pruned, unless the king must be saved
+    let mut moves: Vec<BitMove> = all_moves
+        .iter()
+        .filter(|m| evasions || m.is_capture() || (m.is_promo() && m.promo_piece() == PieceType::Q))
+        .filter(|m| evasions || see(board, **m) >= 0)
+        .cloned()
+        .collect();
+
+    if ctx.move_ordering {
+        MoveOrdering::order_captures(board, &mut moves);
+    }
+
+    let key = board.zobrist();
+
+    for mov in moves {
+        ctx.history.push(key);
+        board.apply_move(mov);
+        ctx.ply += 1;
+        let score = -quiescence(board, -beta, -alpha, qply + 1, eval, ctx);
+        ctx.ply -= 1;
+        board.undo_move();
+        ctx.history.pop();
+
+        if ctx.stopped {
+            return alpha;
+        }
+
+        if score > alpha {
+            alpha = score;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+    }
+
+    alpha
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+pub const MAX_DEPTH: u16 = 64;
+
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+const MOVE_OVERHEAD_MS: u64 = 30;
+
+/// Limits for a single search, every field is optional
+#[derive(Default, Clone)]
+pub struct SearchLimits {
+    pub depth: Option<u16>,
+    /// Fixed time for the move in milliseconds
+    pub movetime: Option<u64>,
+    pub nodes: Option<u64>,
+    /// Remaining clock time of the side to move in milliseconds
+    pub time_left: Option<u64>,
+    pub increment: u64,
+    pub moves_to_go: Option<u64>,
+    pub infinite: bool,
+}
+
+/// How long the search may run.
+/// A new iteration isn't started after `soft`, the running one is aborted at `hard`.
+#[derive(Clone, Copy)]
+pub struct TimeBudget {
+    pub soft: Duration,
+    pub hard: Duration,
+}
+
+impl SearchLimits {
+    pub fn with_depth(depth: u16) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    /// Reads `--depth`, `--movetime` and `--nodes` options of `play` and `test`
+    pub fn from_args(args: &ArgMatches, default_depth: u16) -> Self {
+        let mut limits = Self {
+            depth: args.get_one::<u16>("Depth").cloned(),
+            movetime: args.get_one::<u64>("MoveTime").cloned(),
+            nodes: args.get_one::<u64>("Nodes").cloned(),
+            ..Default::default()
+        };
+
+        if limits.depth == Some(0) {
+            limits.depth = None;
+        }
+
+        if limits.depth.is_none() && limits.movetime.is_none() && limits.nodes.is_none() {
+            limits.depth = Some(default_depth);
+        }
+
+        limits
+    }
+
+    pub fn max_depth(&self) -> u16 {
+        self.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH)
+    }
+
+    pub fn budget(&self) -> Option<TimeBudget> {
+        if self.infinite {
+            return None;
+        }
+
+        if let Some(ms) = self.movetime {
+            let t = Duration::from_millis(ms.saturating_sub(MOVE_OVERHEAD_MS).max(1));
+            return Some(TimeBudget { soft: t, hard: t });
+        }
+
+        self.time_left.map(|left| {
+            let left = left.saturating_sub(MOVE_OVERHEAD_MS).max(1);
+            let moves_to_go = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+            let soft = (left / moves_to_go + self.increment / 2).min(left / 2);
+            let hard = (soft * 4).min(left / 2).max(soft);
+
+            TimeBudget {
+                soft: Duration::from_millis(soft),
+                hard: Duration::from_millis(hard),
+            }
+        })
+    }
+}
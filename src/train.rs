@@ -1,6 +1,7 @@
 use clap::ArgMatches;
-use log::info;
+use log::{info, warn};
 
+use std::error::Error;
 use std::time::Instant;
 
 // nevermind_neu
@@ -10,7 +11,170 @@ use nevermind_neu::models::*;
 use nevermind_neu::optimizers::*;
 use nevermind_neu::util::*;
 
+use crate::bundle::*;
 use crate::dataloader::SqliteChessDataloader;
+use crate::model_cfg::*;
+
+/// Networks of the search, either one per side to move
+/// or a single one (cloned to both fields) which gets the side to move in its input
+#[derive(Clone)]
+pub struct Models<T> {
+    pub white: T,
+    pub black: T,
+    pub side_input: bool,
+}
+
+/// Model states of `--state` or `--state_white` and `--state_black`
+pub fn model_states(args: &ArgMatches) -> Vec<String> {
+    match args.get_one::<String>("ModelState") {
+        Some(state) => vec![state.clone()],
+        None => vec![
+            args.get_one::<String>("ModelStateWhite").unwrap().clone(),
+            args.get_one::<String>("ModelStateBlack").unwrap().clone(),
+        ],
+    }
+}
+
+/// Loads a bundle as `side` network, it's checked and brings its architecture.
+/// Bare state files are refused unless `--allow_bare_state` is given,
+/// then they're built from `--model_cfg` unchecked.
+fn load_state_checked<R>(
+    args: &ArgMatches,
+    state: &str,
+    side: Side,
+    arch: Option<&ModelCfg>,
+    load: impl FnOnce(&str, &ModelCfg) -> Result<R, Box<dyn Error>>,
+) -> Result<R, Box<dyn Error>> {
+    if !Bundle::is_bundle(state)? {
+        if !args.contains_id("AllowBareState") {
+            return Err(format!(
+                "{} is a bare model state without description, pack it with `chess_trainer bundle` \
+                 or pass --allow_bare_state to load it unchecked",
+                state
+            )
+            .into());
+        }
+
+        warn!(
+            "{} is a bare state without model description, it isn't checked for compatibility",
+            state
+        );
+        return load(state, &arch.cloned().unwrap_or_default());
+    }
+
+    let bundle = Bundle::open(state)?;
+    bundle.meta.check(state, side, arch)?;
+
+    let provenance = &bundle.meta.provenance;
+    info!(
+        "Loading model {} trained on {} for {} epochs",
+        state,
+        provenance.dataset.as_deref().unwrap_or("unknown dataset"),
+        provenance.epochs.map_or("unknown".to_string(), |e| e.to_string())
+    );
+
+    bundle.with_weights(|weights| load(weights, &bundle.meta.arch))
+}
+
+/// `load` builds a model of the architecture with the given input size and loads the state into it
+fn load_models_with<T: Clone>(
+    args: &ArgMatches,
+    load: impl Fn(&str, &ModelCfg, usize) -> Result<T, Box<dyn Error>>,
+) -> Result<Models<T>, Box<dyn Error>> {
+    let states = model_states(args);
+    let arch = ModelCfg::from_args(args)?;
+
+    let load_side = |state: &str, side: Side| {
+        load_state_checked(args, state, side, arch.as_ref(), |weights, cfg| {
+            load(weights, cfg, side.input_size())
+        })
+    };
+
+    if let [state] = states.as_slice() {
+        let mdl = load_side(state, Side::Both)?;
+
+        return Ok(Models {
+            white: mdl.clone(),
+            black: mdl,
+            side_input: true,
+        });
+    }
+
+    Ok(Models {
+        white: load_side(&states[0], Side::White)?,
+        black: load_side(&states[1], Side::Black)?,
+        side_input: false,
+    })
+}
+
+pub fn load_models(args: &ArgMatches) -> Result<Models<Sequential>, Box<dyn Error>> {
+    load_models_with(args, |state, cfg, input_size| {
+        let mut mdl = Sequential::new();
+        fill_model_with_layers(&mut mdl, cfg, false, input_size);
+        mdl.load_state(state)?;
+
+        Ok(mdl)
+    })
+}
+
+pub fn load_ocl_models(args: &ArgMatches) -> Result<Models<SequentialOcl>, Box<dyn Error>> {
+    load_models_with(args, |state, cfg, input_size| {
+        let mut mdl = SequentialOcl::new()?;
+        fill_ocl_model_with_layers(&mut mdl, cfg, false, input_size);
+        mdl.load_state(state)?;
+
+        Ok(mdl)
+    })
+}
+
+/// Description of the model trained on `dataset`, bundled with its weights in `--out`.
+/// A bundled state the training continues from must describe the same model.
+fn train_meta(
+    args: &ArgMatches,
+    dataset: &SqliteChessDataloader,
+) -> Result<ModelMeta, Box<dyn Error>> {
+    let arch = ModelCfg::from_args(args)?;
+    let parent = args.get_one::<String>("State");
+
+    let side = Side::from_args(args);
+
+    // side of the continued model is kept unless it's given
+    let (arch, side) = match parent {
+        Some(state) if Bundle::is_bundle(state)? => {
+            let meta = Bundle::open(state)?.meta;
+            let side = side.unwrap_or(meta.side);
+
+            meta.check(state, side, arch.as_ref())?;
+            (meta.arch, side)
+        }
+        _ => (
+            arch.unwrap_or_default(),
+            side.ok_or("--side or --side_to_move is required, the model bundle records it")?,
+        ),
+    };
+
+    let provenance = Provenance::new(
+        args.get_one::<String>("Dataset").cloned(),
+        args.get_one::<usize>("EpochsNum").copied(),
+        parent.cloned(),
+    );
+
+    Ok(ModelMeta::new(arch, side, dataset.label_limit, provenance))
+}
+
+/// Writes the trained weights with their description to `--out`
+fn save_bundle(
+    args: &ArgMatches,
+    meta: ModelMeta,
+    save: impl FnOnce(&str) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let out = args.get_one::<String>("Out").unwrap();
+
+    Bundle::from_saved(meta, save)?.save(out)?;
+    info!("Model bundle is saved to {}", out);
+
+    Ok(())
+}
 
 pub fn train_new(args: &ArgMatches, is_ocl: bool) -> Result<(), Box<dyn std::error::Error>> {
     if is_ocl {
@@ -22,47 +186,95 @@ pub fn train_new(args: &ArgMatches, is_ocl: bool) -> Result<(), Box<dyn std::err
     Ok(())
 }
 
-pub fn fill_model_with_layers(mdl: &mut Sequential, add_dropout: bool)
-{
-    let input_layer = InputLayer::new_box(898); // 8 * 8 * 14 + 2
+pub fn fill_model_with_layers(
+    mdl: &mut Sequential,
+    cfg: &ModelCfg,
+    add_dropout: bool,
+    input_size: usize,
+) {
+    let input_layer = InputLayer::new_box(input_size);
     mdl.add_layer(input_layer);
 
-    for i in 0..4 {
-        let mut fc_layer = FcLayer::new_box(900 - i * 100, leaky_relu_activation!());
+    for (i, layer) in cfg.layers.iter().enumerate() {
+        let dropout = if add_dropout { cfg.layer_dropout(i) } else { 0.0 };
+
+        // each activation is a distinct layer type
+        macro_rules! add_fc_layer {
+            ($act:expr) => {{
+                let mut fc_layer = FcLayer::new_box(layer.size, $act);
 
-        if add_dropout {
-            fc_layer.set_dropout(0.13);
+                if dropout > 0.0 {
+                    fc_layer.set_dropout(dropout);
+                }
+
+                mdl.add_layer(fc_layer);
+            }};
         }
 
-        mdl.add_layer(fc_layer);
+        match layer.activation {
+            Activation::LeakyRelu => add_fc_layer!(leaky_relu_activation!()),
+            Activation::Relu => add_fc_layer!(relu_activation!()),
+            Activation::Sigmoid => add_fc_layer!(sigmoid_activation!()),
+            Activation::Tanh => add_fc_layer!(tanh_activation!()),
+            Activation::Raw => add_fc_layer!(raw_activation!()),
+        }
     }
 
-    let euc_err_layer = EuclideanLossLayer::new_box(1, sigmoid_activation!());
-    mdl.add_layer(euc_err_layer);
+    match cfg.loss {
+        Loss::Euclidean => match cfg.output_activation {
+            Activation::LeakyRelu => {
+                mdl.add_layer(EuclideanLossLayer::new_box(1, leaky_relu_activation!()))
+            }
+            Activation::Relu => mdl.add_layer(EuclideanLossLayer::new_box(1, relu_activation!())),
+            Activation::Sigmoid => {
+                mdl.add_layer(EuclideanLossLayer::new_box(1, sigmoid_activation!()))
+            }
+            Activation::Tanh => mdl.add_layer(EuclideanLossLayer::new_box(1, tanh_activation!())),
+            Activation::Raw => mdl.add_layer(EuclideanLossLayer::new_box(1, raw_activation!())),
+        },
+    }
 
     mdl.compile_shapes(); // do not forget to call after layers were added
 }
 
-pub fn fill_ocl_model_with_layers(mdl: &mut SequentialOcl, add_dropout: bool) 
-{
-    let input_layer = Box::new(InputLayerOcl::new(898)); // 8 * 8 * 14 + 2
+fn ocl_activation(activation: Activation) -> OclActivationFunc {
+    match activation {
+        Activation::LeakyRelu => OclActivationFunc::LeakyReLU,
+        Activation::Relu => OclActivationFunc::ReLU,
+        Activation::Sigmoid => OclActivationFunc::Sigmoid,
+        Activation::Tanh => OclActivationFunc::Tanh,
+        Activation::Raw => OclActivationFunc::Raw,
+    }
+}
+
+pub fn fill_ocl_model_with_layers(
+    mdl: &mut SequentialOcl,
+    cfg: &ModelCfg,
+    add_dropout: bool,
+    input_size: usize,
+) {
+    let input_layer = Box::new(InputLayerOcl::new(input_size));
     // TODO : maybe add constructor like InputDataLayer::new_box
     mdl.add_layer(input_layer);
 
-    for i in 0..4 {
-        let mut fc_layer = Box::new(FcLayerOcl::new(900 - i * 100, OclActivationFunc::LeakyReLU));
+    for (i, layer) in cfg.layers.iter().enumerate() {
+        let mut fc_layer = Box::new(FcLayerOcl::new(layer.size, ocl_activation(layer.activation)));
 
-        if add_dropout {
-           fc_layer.set_dropout(0.13);
+        if add_dropout && cfg.layer_dropout(i) > 0.0 {
+            fc_layer.set_dropout(cfg.layer_dropout(i));
         }
 
         mdl.add_layer(fc_layer);
     }
 
-    let mut euc_err_layer = Box::new(EuclideanLossLayerOcl::new(1));
-    euc_err_layer.set_activation_function(OclActivationFunc::Sigmoid);
+    match cfg.loss {
+        Loss::Euclidean => {
+            let mut euc_err_layer = Box::new(EuclideanLossLayerOcl::new(1));
+            euc_err_layer.set_activation_function(ocl_activation(cfg.output_activation));
 
-    mdl.add_layer(euc_err_layer);
+            mdl.add_layer(euc_err_layer);
+        }
+    }
 
     mdl.init_layers(); // TODO : maybe rename  same as Sequential like compile_shapes(...)
 }
@@ -74,11 +286,22 @@ pub fn train_chess(args: &ArgMatches) -> Result<(), Box<dyn std::error::Error>>
     let mut dataset = Box::new(SqliteChessDataloader::new(ds_path.as_str()));
     dataset.do_shuffle = true;
 
+    let meta = train_meta(args, &dataset)?;
+    dataset.side_input = meta.side == Side::Both;
+
     let mut mdl = Sequential::new();
 
-    fill_model_with_layers(&mut mdl, true);
+    fill_model_with_layers(&mut mdl, &meta.arch, true, meta.encoding.input_size);
     mdl.set_batch_size(16);
 
+    if let Some(state) = args.get_one::<String>("State") {
+        info!("Loading model state from {}", state);
+        load_state_checked(args, state, meta.side, Some(&meta.arch), |weights, _| {
+            mdl.load_state(weights)?;
+            Ok(())
+        })?;
+    }
+
     // Optimizer
     {
         let opt = Box::new(OptimizerAdam::new(7e-4));
@@ -86,18 +309,21 @@ pub fn train_chess(args: &ArgMatches) -> Result<(), Box<dyn std::error::Error>>
     }
 
     let mut net = Orchestra::new(mdl);
+    net.name = args.get_one::<String>("Out").unwrap().clone();
 
     net.set_train_dataset(dataset);
     net.set_snap_iter(200_000);
     net.set_learn_rate_decay(0.7);
     net.set_learn_rate_decay_step(200_000);
     net.set_write_err_to_file(true);
-    net.set_save_on_finish_flag(true);
+    net.set_save_on_finish_flag(false); // the bundle is saved instead
 
     let now = Instant::now();
     net.train_epochs_or_error(*epochs, 1e-3)?;
     info!("Training finished, elapsed : {} seconds", now.elapsed().as_secs());
 
+    save_bundle(args, meta, |path| net.save_model_state(path))?;
+
     Ok(())
 }
 
@@ -108,14 +334,20 @@ pub fn train_chess_ocl(args: &ArgMatches) -> Result<(), Box<dyn std::error::Erro
 
     let epochs = args.get_one::<usize>("EpochsNum").unwrap();
 
+    let meta = train_meta(args, &dataset)?;
+    dataset.side_input = meta.side == Side::Both;
+
     let mut mdl = SequentialOcl::new()?;
 
-    fill_ocl_model_with_layers(&mut mdl, true);
+    fill_ocl_model_with_layers(&mut mdl, &meta.arch, true, meta.encoding.input_size);
     mdl.set_batch_size(16);
 
     if let Some(state) = args.get_one::<String>("State") {
         info!("Loading model state from {}", state);
-        mdl.load_state(state)?;
+        load_state_checked(args, state, meta.side, Some(&meta.arch), |weights, _| {
+            mdl.load_state(weights)?;
+            Ok(())
+        })?;
     }
 
     // Optimizer
@@ -132,12 +364,14 @@ pub fn train_chess_ocl(args: &ArgMatches) -> Result<(), Box<dyn std::error::Erro
     net.set_learn_rate_decay(0.7);
     net.set_learn_rate_decay_step(200_000);
     net.set_write_err_to_file(true);
-    net.set_save_on_finish_flag(true);
+    net.set_save_on_finish_flag(false); // the bundle is saved instead
 
     let now = Instant::now();
     net.train_epochs_or_error(*epochs, 1e-3)?;
     info!("Training finished, elapsed : {} seconds", now.elapsed().as_secs());
 
+    save_bundle(args, meta, |path| net.save_model_state(path))?;
+
     Ok(())
 }
 
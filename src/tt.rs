@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use pleco::BitMove;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// How the stored score relates to the real score of the position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+    Exact,
+    /// Search failed high, real score is at least `score`
+    Lower,
+    /// Search failed low, real score is at most `score`
+    Upper,
+}
+
+impl Bound {
+    /// Zero is left for empty slots
+    fn to_bits(self) -> u64 {
+        match self {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            1 => Some(Bound::Exact),
+            2 => Some(Bound::Lower),
+            3 => Some(Bound::Upper),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TtEntry {
+    pub key: u64,
+    pub best_move: BitMove,
+    pub score: i16,
+    pub depth: u16,
+    pub bound: Bound,
+    generation: u8,
+}
+
+impl TtEntry {
+    /// move : 16 | score : 16 | depth : 16 | bound : 8 | generation : 8
+    fn pack(&self) -> u64 {
+        self.best_move.get_raw() as u64
+            | (self.score as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | self.bound.to_bits() << 48
+            | (self.generation as u64) << 56
+    }
+
+    fn unpack(key: u64, data: u64) -> Option<Self> {
+        Some(Self {
+            key,
+            best_move: BitMove::new(data as u16),
+            score: (data >> 16) as u16 as i16,
+            depth: (data >> 32) as u16,
+            bound: Bound::from_bits((data >> 48) & 0xff)?,
+            generation: (data >> 56) as u8,
+        })
+    }
+}
+
+/// Slot keeps `key ^ data`, so an entry torn by a concurrent write
+/// doesn't match its key and is treated as a miss
+#[derive(Default)]
+struct Slot {
+    key_xor: AtomicU64,
+    data: AtomicU64,
+}
+
+/// Fixed-size transposition table keyed by `Board::zobrist()`.
+/// Each key has a single slot, entries of older searches or with less depth get replaced.
+/// It's lock-free, so searcher threads share a single table.
+#[derive(Default)]
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let mut tt = Self::default();
+        tt.resize(size_mb);
+        tt
+    }
+
+    /// Reallocates the table, all entries are lost
+    pub fn resize(&mut self, size_mb: usize) {
+        let cnt = size_mb * 1024 * 1024 / std::mem::size_of::<Slot>();
+
+        self.slots = (0..cnt).map(|_| Slot::default()).collect();
+        self.generation = AtomicU8::new(0);
+    }
+
+    pub fn clear(&self) {
+        for s in self.slots.iter() {
+            s.key_xor.store(0, Ordering::Relaxed);
+            s.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Must be called before each search, so entries of previous searches are replaced first
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn index(&self, key: u64) -> usize {
+        ((key as u128 * self.slots.len() as u128) >> 64) as usize
+    }
+
+    fn load(&self, idx: usize) -> Option<TtEntry> {
+        let slot = &self.slots[idx];
+        let data = slot.data.load(Ordering::Relaxed);
+        let key = slot.key_xor.load(Ordering::Relaxed) ^ data;
+
+        TtEntry::unpack(key, data)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TtEntry> {
+        if self.slots.is_empty() {
+            return None;
+        }
+
+        self.load(self.index(key)).filter(|e| e.key == key)
+    }
+
+    pub fn store(&self, key: u64, depth: u16, bound: Bound, score: i16, best_move: BitMove) {
+        if self.slots.is_empty() {
+            return;
+        }
+
+        let idx = self.index(key);
+        let generation = self.generation.load(Ordering::Relaxed);
+        let old = self.load(idx);
+
+        let replace = match &old {
+            None => true,
+            Some(e) if e.key == key => depth >= e.depth || bound == Bound::Exact,
+            Some(e) => e.generation != generation || depth >= e.depth,
+        };
+
+        if !replace {
+            return;
+        }
+
+        // keep the known best move if the new search has none
+        let best_move = match &old {
+            Some(e) if e.key == key && best_move.is_null() => e.best_move,
+            _ => best_move,
+        };
+
+        let data = TtEntry {
+            key,
+            best_move,
+            score,
+            depth,
+            bound,
+            generation,
+        }
+        .pack();
+
+        let slot = &self.slots[idx];
+        slot.key_xor.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    /// Table usage in permille as expected by the uci `hashfull` info
+    pub fn hashfull(&self) -> u16 {
+        let sample = self.slots.len().min(1000);
+
+        if sample == 0 {
+            return 0;
+        }
+
+        let generation = self.generation.load(Ordering::Relaxed);
+        let used = (0..sample)
+            .filter(|i| matches!(self.load(*i), Some(e) if e.generation == generation))
+            .count();
+
+        (used * 1000 / sample) as u16
+    }
+}
@@ -0,0 +1,321 @@
+use clap::ArgMatches;
+use log::{info, warn};
+
+use serde::Serialize;
+use std::error::Error;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+
+use nevermind_neu::models::*;
+
+use pleco::{Board, Player};
+
+use crate::engine_io::*;
+use crate::evaluator::Evaluator;
+use crate::syzygy::Syzygy;
+use crate::test::*;
+use crate::timeman::*;
+use crate::train::*;
+use crate::tt::DEFAULT_HASH_MB;
+
+const ENGINE_NAME: &str = "chess_trainer";
+const ENGINE_AUTHOR: &str = "xion";
+
+const DEFAULT_DEPTH: u16 = 4;
+/// Commands aborting the running search at once
+const STOP_COMMANDS: &[&str] = &["stop", "quit"];
+/// Commands starting a search
+const SEARCH_COMMANDS: &[&str] = &["go"];
+const MAX_HASH_MB: usize = 4096;
+const MAX_MULTIPV: usize = 64;
+
+/// `cp <x>` or `mate <moves>` part of the `info` command
+fn uci_score(score: i16) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+/// Parses limits of the `go` command for the side to move
+fn parse_go(tokens: &[&str], side: Player) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let (mut wtime, mut btime, mut winc, mut binc) = (None, None, 0, 0);
+    let mut it = tokens.iter();
+
+    while let Some(tok) = it.next() {
+        let mut next_num = || it.next().and_then(|v| v.parse::<u64>().ok());
+
+        match *tok {
+            "depth" => limits.depth = next_num().map(|d| d as u16),
+            "nodes" => limits.nodes = next_num(),
+            "movetime" => limits.movetime = next_num(),
+            "wtime" => wtime = next_num(),
+            "btime" => btime = next_num(),
+            "winc" => winc = next_num().unwrap_or(0),
+            "binc" => binc = next_num().unwrap_or(0),
+            "movestogo" => limits.moves_to_go = next_num(),
+            "infinite" => limits.infinite = true,
+            _ => {}
+        }
+    }
+
+    match side {
+        Player::White => {
+            limits.time_left = wtime;
+            limits.increment = winc;
+        }
+        Player::Black => {
+            limits.time_left = btime;
+            limits.increment = binc;
+        }
+    }
+
+    limits
+}
+
+pub fn uci(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_models(args)?;
+
+    continue_uci(models)
+}
+
+pub fn uci_ocl(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_ocl_models(args)?;
+
+    continue_uci(models)
+}
+
+/// Commands received in the middle of a search
+enum Interrupt {
+    None,
+    Stop,
+    Quit,
+}
+
+struct UciState<T: Model + Serialize + Clone> {
+    eval: Evaluator<T>,
+    board: Board,
+    depth: u16,
+    ctx: SearchCtx,
+    pending: Vec<String>,
+}
+
+pub fn continue_uci<T: Model + Serialize + Clone>(models: Models<T>) -> Result<(), Box<dyn Error>> {
+    let mut state = UciState {
+        eval: Evaluator::new(models),
+        board: Board::start_pos(),
+        depth: DEFAULT_DEPTH,
+        ctx: SearchCtx::with_hash(DEFAULT_HASH_MB),
+        pending: Vec::new(),
+    };
+
+    let rx = spawn_stdin_reader(state.ctx.stop.clone(), STOP_COMMANDS, SEARCH_COMMANDS);
+
+    loop {
+        let line = if !state.pending.is_empty() {
+            state.pending.remove(0)
+        } else {
+            match rx.recv() {
+                Ok(l) => l,
+                Err(_) => break, // stdin closed
+            }
+        };
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "uci" => {
+                send(&format!("id name {}", ENGINE_NAME));
+                send(&format!("id author {}", ENGINE_AUTHOR));
+                send(&format!(
+                    "option name Depth type spin default {} min 1 max {}",
+                    DEFAULT_DEPTH, MAX_DEPTH
+                ));
+                send(&format!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_MB, MAX_HASH_MB
+                ));
+                send(&format!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                ));
+                send("option name SyzygyPath type string default <empty>");
+                send("uciok");
+            }
+            "isready" => send("readyok"),
+            "setoption" => set_option(&mut state, &tokens[1..]),
+            "ucinewgame" => {
+                state.board = Board::start_pos();
+                state.ctx.new_game();
+            }
+            "position" => match parse_position(&tokens[1..]) {
+                Some(b) => state.board = b,
+                None => warn!("Invalid position command : {}", line),
+            },
+            "go" => {
+                let limits = parse_go(&tokens[1..], state.board.turn());
+                if let Interrupt::Quit = go(&mut state, &limits, &rx) {
+                    break;
+                }
+            }
+            "stop" => {} // search is not running
+            "quit" => break,
+            _ => warn!("Unknown uci command : {}", line),
+        }
+    }
+
+    Ok(())
+}
+
+fn set_option<T: Model + Serialize + Clone>(state: &mut UciState<T>, tokens: &[&str]) {
+    // setoption name <id> [value <x>]
+    let value_pos = tokens.iter().position(|t| *t == "value");
+    let name = match value_pos {
+        Some(p) => tokens[1..p].join(" "),
+        None => tokens.get(1..).unwrap_or(&[]).join(" "),
+    };
+    let value = value_pos.map(|p| tokens[p + 1..].join(" "));
+
+    match (name.to_lowercase().as_str(), value) {
+        ("depth", Some(v)) => match v.parse::<u16>() {
+            Ok(d) => state.depth = d.clamp(1, MAX_DEPTH),
+            Err(_) => warn!("Invalid Depth value : {}", v),
+        },
+        ("hash", Some(v)) => match v.parse::<usize>() {
+            Ok(mb) => state.ctx.resize_hash(mb.clamp(1, MAX_HASH_MB)),
+            Err(_) => warn!("Invalid Hash value : {}", v),
+        },
+        ("multipv", Some(v)) => match v.parse::<usize>() {
+            Ok(n) => state.ctx.multipv = n.clamp(1, MAX_MULTIPV),
+            Err(_) => warn!("Invalid MultiPV value : {}", v),
+        },
+        ("syzygypath", Some(v)) if v.is_empty() || v == "<empty>" => state.ctx.syzygy = None,
+        ("syzygypath", Some(v)) => match Syzygy::open(&v) {
+            Ok(tb) => state.ctx.syzygy = Some(Arc::new(tb)),
+            Err(e) => warn!("Invalid SyzygyPath value : {}", e),
+        },
+        _ => warn!("Unknown option : {}", name),
+    }
+}
+
+fn parse_position(tokens: &[&str]) -> Option<Board> {
+    let moves_pos = tokens.iter().position(|t| *t == "moves");
+    let setup = &tokens[..moves_pos.unwrap_or(tokens.len())];
+
+    let mut board = match setup.first() {
+        Some(&"startpos") => Board::start_pos(),
+        Some(&"fen") => Board::from_fen(&setup[1..].join(" ")).ok()?,
+        _ => return None,
+    };
+
+    if let Some(p) = moves_pos {
+        for m in tokens[p + 1..].iter() {
+            if !board.apply_uci_move(m) {
+                warn!("Illegal move in position command : {}", m);
+                return None;
+            }
+        }
+    }
+
+    Some(board)
+}
+
+/// Checks stdin for commands that could arrive while searching
+fn poll_interrupt(pending: &mut Vec<String>, rx: &Receiver<String>) -> Interrupt {
+    loop {
+        match rx.try_recv() {
+            Ok(line) => match line.trim() {
+                "stop" => return Interrupt::Stop,
+                "quit" => return Interrupt::Quit,
+                "isready" => send("readyok"),
+                _ => pending.push(line),
+            },
+            Err(TryRecvError::Empty) => return Interrupt::None,
+            Err(TryRecvError::Disconnected) => return Interrupt::Quit,
+        }
+    }
+}
+
+fn go<T: Model + Serialize + Clone>(
+    state: &mut UciState<T>,
+    limits: &SearchLimits,
+    rx: &Receiver<String>,
+) -> Interrupt {
+    let mut limits = limits.clone();
+
+    if limits.depth.is_none()
+        && limits.nodes.is_none()
+        && limits.budget().is_none()
+        && !limits.infinite
+    {
+        limits.depth = Some(state.depth);
+    }
+
+    if state.board.generate_moves().is_empty() {
+        send("bestmove 0000");
+        return Interrupt::None;
+    }
+
+    // keeps the move history for repetition detection
+    let mut board = state.board.parallel_clone();
+    let mut interrupt = Interrupt::None;
+
+    let best_move = {
+        let UciState {
+            eval, ctx, pending, ..
+        } = state;
+
+        iterative_deepening(&mut board, &limits, eval, ctx, |it| {
+            for (i, line) in it.lines.iter().enumerate() {
+                send(&format!(
+                    "info depth {} multipv {} score {} nodes {} time {} pv {}",
+                    it.depth,
+                    i + 1,
+                    uci_score(line.score),
+                    it.nodes,
+                    it.elapsed.as_millis(),
+                    line.moves_str()
+                ));
+            }
+
+            interrupt = poll_interrupt(pending, rx);
+            matches!(interrupt, Interrupt::None)
+        })
+    };
+
+    // in infinite mode bestmove must not be sent before stop
+    while limits.infinite && matches!(interrupt, Interrupt::None) {
+        interrupt = match rx.recv() {
+            Ok(line) => match line.trim() {
+                "stop" => Interrupt::Stop,
+                "quit" => Interrupt::Quit,
+                "isready" => {
+                    send("readyok");
+                    Interrupt::None
+                }
+                _ => {
+                    state.pending.push(line);
+                    Interrupt::None
+                }
+            },
+            Err(_) => Interrupt::Quit,
+        };
+    }
+
+    info!("Search finished with {} nodes", state.ctx.nodes);
+    send(&format!("info hashfull {}", state.ctx.tt.hashfull()));
+    send(&format!(
+        "info string evalcache hits {} misses {} hitrate {:.1}",
+        state.ctx.eval_cache.hits,
+        state.ctx.eval_cache.misses,
+        state.ctx.eval_cache.hit_rate()
+    ));
+    send(&format!("bestmove {}", best_move.bit_move));
+
+    interrupt
+}
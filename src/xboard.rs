@@ -0,0 +1,370 @@
+use clap::ArgMatches;
+use log::{info, warn};
+
+use serde::Serialize;
+use std::error::Error;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+
+use nevermind_neu::models::*;
+
+use pleco::{Board, Player};
+
+use crate::engine_io::*;
+use crate::evaluator::Evaluator;
+use crate::syzygy::Syzygy;
+use crate::test::*;
+use crate::timeman::*;
+use crate::train::*;
+use crate::tt::DEFAULT_HASH_MB;
+
+const ENGINE_NAME: &str = "chess_trainer";
+
+const DEFAULT_DEPTH: u16 = 4;
+/// Commands aborting the running search at once
+const STOP_COMMANDS: &[&str] = &["?", "quit"];
+/// Commands which may start a search
+const SEARCH_COMMANDS: &[&str] = &["go", "usermove"];
+
+pub fn xboard(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_models(args)?;
+
+    continue_xboard(models)
+}
+
+pub fn xboard_ocl(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let models = load_ocl_models(args)?;
+
+    continue_xboard(models)
+}
+
+/// Time control set with `level` or `st`
+enum TimeControl {
+    Conventional { mps: u64, base_ms: u64, inc_ms: u64 },
+    FixedPerMove { ms: u64 },
+}
+
+struct XboardState<T: Model + Serialize + Clone> {
+    eval: Evaluator<T>,
+    board: Board,
+    /// Moves applied since the last `new` or `setboard`, which could be undone
+    undo_count: usize,
+    /// Side played by the engine, none in force mode
+    engine_side: Option<Player>,
+    max_depth: Option<u16>,
+    time_control: Option<TimeControl>,
+    /// Engine clock in milliseconds, updated by `time`
+    clock_ms: Option<u64>,
+    post: bool,
+    ctx: SearchCtx,
+    pending: Vec<String>,
+}
+
+impl<T: Model + Serialize + Clone> XboardState<T> {
+    fn reset_board(&mut self, board: Board) {
+        self.board = board;
+        self.undo_count = 0;
+    }
+
+    fn undo(&mut self) {
+        if self.undo_count > 0 {
+            self.board.undo_move();
+            self.undo_count -= 1;
+        } else {
+            warn!("Nothing to undo");
+        }
+    }
+
+    /// Search limits for the next engine move
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.max_depth,
+            ..Default::default()
+        };
+
+        match self.time_control {
+            Some(TimeControl::FixedPerMove { ms }) => limits.movetime = Some(ms),
+            Some(TimeControl::Conventional {
+                mps,
+                base_ms,
+                inc_ms,
+            }) => {
+                limits.time_left = Some(self.clock_ms.unwrap_or(base_ms));
+                limits.increment = inc_ms;
+
+                if mps > 0 {
+                    let played = self.board.moves_played() as u64 / 2;
+                    limits.moves_to_go = Some(mps - played % mps);
+                }
+            }
+            None => {
+                if limits.depth.is_none() {
+                    limits.depth = Some(DEFAULT_DEPTH);
+                }
+            }
+        }
+
+        limits
+    }
+}
+
+pub fn continue_xboard<T: Model + Serialize + Clone>(
+    models: Models<T>,
+) -> Result<(), Box<dyn Error>> {
+    let mut state = XboardState {
+        eval: Evaluator::new(models),
+        board: Board::start_pos(),
+        undo_count: 0,
+        engine_side: Some(Player::Black),
+        max_depth: None,
+        time_control: None,
+        clock_ms: None,
+        post: false,
+        ctx: SearchCtx::with_hash(DEFAULT_HASH_MB),
+        pending: Vec::new(),
+    };
+
+    let rx = spawn_stdin_reader(state.ctx.stop.clone(), STOP_COMMANDS, SEARCH_COMMANDS);
+
+    loop {
+        let line = if !state.pending.is_empty() {
+            state.pending.remove(0)
+        } else {
+            match rx.recv() {
+                Ok(l) => l,
+                Err(_) => break, // stdin closed
+            }
+        };
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "xboard" => {}
+            "protover" => send(&format!(
+                "feature myname=\"{}\" usermove=1 setboard=1 ping=1 memory=1 egt=\"syzygy\" sigint=0 sigterm=0 colors=0 analyze=0 done=1",
+                ENGINE_NAME
+            )),
+            "accepted" | "rejected" => {}
+            "new" => {
+                state.reset_board(Board::start_pos());
+                state.engine_side = Some(Player::Black);
+                state.max_depth = None;
+                state.ctx.new_game();
+            }
+            "force" => state.engine_side = None,
+            "go" => {
+                state.engine_side = Some(state.board.turn());
+                if !engine_move(&mut state, &rx) {
+                    break;
+                }
+            }
+            "usermove" => {
+                let mv = match tokens.get(1) {
+                    Some(m) => *m,
+                    None => continue,
+                };
+
+                if !state.board.apply_uci_move(mv) {
+                    send(&format!("Illegal move: {}", mv));
+                    continue;
+                }
+                state.undo_count += 1;
+
+                if report_game_end(&state.board) {
+                    state.engine_side = None;
+                    continue;
+                }
+
+                if state.engine_side == Some(state.board.turn()) && !engine_move(&mut state, &rx) {
+                    break;
+                }
+            }
+            "setboard" => match Board::from_fen(&tokens[1..].join(" ")) {
+                Ok(b) => state.reset_board(b),
+                Err(_) => send("tellusererror Illegal position"),
+            },
+            "level" => match parse_level(&tokens[1..]) {
+                Some(tc) => state.time_control = Some(tc),
+                None => warn!("Invalid level command : {}", line),
+            },
+            "st" => match tokens.get(1).and_then(|v| v.parse::<u64>().ok()) {
+                Some(sec) => state.time_control = Some(TimeControl::FixedPerMove { ms: sec * 1000 }),
+                None => warn!("Invalid st command : {}", line),
+            },
+            "sd" => match tokens.get(1).and_then(|v| v.parse::<u16>().ok()) {
+                Some(d) => state.max_depth = Some(d.clamp(1, MAX_DEPTH)),
+                None => warn!("Invalid sd command : {}", line),
+            },
+            "time" => {
+                // centiseconds
+                state.clock_ms = tokens.get(1).and_then(|v| v.parse::<u64>().ok()).map(|cs| cs * 10);
+            }
+            "memory" => match tokens.get(1).and_then(|v| v.parse::<usize>().ok()) {
+                Some(mb) => state.ctx.resize_hash(mb.max(1)),
+                None => warn!("Invalid memory command : {}", line),
+            },
+            "egtpath" => match tokens.get(1..3) {
+                Some(["syzygy", path]) => match Syzygy::open(path) {
+                    Ok(tb) => state.ctx.syzygy = Some(Arc::new(tb)),
+                    Err(e) => warn!("Tablebases are disabled : {}", e),
+                },
+                _ => warn!("Unsupported egtpath command : {}", line),
+            },
+            "undo" => state.undo(),
+            "remove" => {
+                state.undo();
+                state.undo();
+            }
+            "result" => state.engine_side = None,
+            "ping" => send(&format!("pong {}", tokens.get(1).unwrap_or(&""))),
+            "post" => state.post = true,
+            "nopost" => state.post = false,
+            "quit" => break,
+            "otim" | "random" | "hard" | "easy" | "computer" | "name" | "rating" | "ics"
+            | "white" | "black" | "?" => {}
+            _ => warn!("Unknown xboard command : {}", line),
+        }
+    }
+
+    Ok(())
+}
+
+/// Centipawns, mates are reported as 100000 + moves by convention
+fn xboard_score(score: i16) -> i32 {
+    match mate_in(score) {
+        Some(moves) if moves > 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => score as i32,
+    }
+}
+
+/// Parses `level MPS BASE INC`, where BASE is minutes or minutes:seconds
+fn parse_level(tokens: &[&str]) -> Option<TimeControl> {
+    if tokens.len() < 3 {
+        return None;
+    }
+
+    let mps = tokens[0].parse::<u64>().ok()?;
+
+    let mut base = tokens[1].split(':');
+    let minutes = base.next()?.parse::<u64>().ok()?;
+    let seconds = match base.next() {
+        Some(s) => s.parse::<u64>().ok()?,
+        None => 0,
+    };
+
+    let inc = tokens[2].parse::<f64>().ok()?;
+
+    Some(TimeControl::Conventional {
+        mps,
+        base_ms: (minutes * 60 + seconds) * 1000,
+        inc_ms: (inc * 1000.0) as u64,
+    })
+}
+
+/// Sends the result command if the game is over
+fn report_game_end(b: &Board) -> bool {
+    match game_end(b) {
+        None => false,
+        Some(GameEnd::Checkmate) => {
+            if b.turn() == Player::White {
+                send("0-1 {Black mates}");
+            } else {
+                send("1-0 {White mates}");
+            }
+            true
+        }
+        Some(end) => {
+            send(&format!("1/2-1/2 {{{}}}", end.describe()));
+            true
+        }
+    }
+}
+
+/// Searches and plays the engine's move, returns false on `quit`
+fn engine_move<T: Model + Serialize + Clone>(
+    state: &mut XboardState<T>,
+    rx: &Receiver<String>,
+) -> bool {
+    if report_game_end(&state.board) {
+        state.engine_side = None;
+        return true;
+    }
+
+    let limits = state.limits();
+    // keeps the move history for repetition detection
+    let mut board = state.board.parallel_clone();
+    let mut quit = false;
+
+    let best_move = {
+        let XboardState {
+            eval,
+            post,
+            ctx,
+            pending,
+            ..
+        } = state;
+
+        iterative_deepening(&mut board, &limits, eval, ctx, |it| {
+            if *post {
+                // ply score time(centiseconds) nodes pv
+                send(&format!(
+                    "{} {} {} {} {}",
+                    it.depth,
+                    xboard_score(it.score),
+                    it.elapsed.as_millis() / 10,
+                    it.nodes,
+                    it.lines.first().map(|l| l.moves_str()).unwrap_or_default()
+                ));
+            }
+
+            let mut move_now = false;
+            loop {
+                match rx.try_recv() {
+                    Ok(line) => match line.trim() {
+                        "?" => move_now = true,
+                        "quit" => quit = true,
+                        _ => pending.push(line),
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        quit = true;
+                        break;
+                    }
+                }
+            }
+
+            !move_now && !quit
+        })
+        .bit_move
+    };
+
+    if quit {
+        return false;
+    }
+
+    info!("Search finished with {} nodes", state.ctx.nodes);
+    state.ctx.eval_cache.log_stats();
+
+    let best_move = match playable_move(&state.board, best_move) {
+        Some(mv) => mv,
+        None => {
+            state.engine_side = None;
+            return true;
+        }
+    };
+
+    state.board.apply_move(best_move);
+    state.undo_count += 1;
+    send(&format!("move {}", best_move));
+
+    if report_game_end(&state.board) {
+        state.engine_side = None;
+    }
+
+    true
+}